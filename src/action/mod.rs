@@ -0,0 +1,468 @@
+use crate::checksum;
+use crate::journal;
+use crate::shutdown;
+use crate::webhook;
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("An IO error was thrown while trying to {0}")]
+    Io(String, #[source] io::Error),
+    #[error("The {0} action requires a destination")]
+    MissingDestination(Operation),
+    #[error("The destination '{0}' already exists")]
+    Conflict(PathBuf),
+    #[error("A checksum error was thrown while comparing a file to its destination")]
+    Checksum(#[from] checksum::Error),
+    #[error("The webhook action requires a request to send")]
+    MissingWebhook,
+    #[error(transparent)]
+    Webhook(#[from] webhook::Error),
+    #[error("An error was thrown while recording a change in the journal")]
+    Journal(#[from] journal::Error),
+    #[error("The action wasn't run since Librarian is shutting down")]
+    Stopped,
+}
+
+/// A file operation that Librarian can run without using a shell
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// Moves the file to the destination, copying then deleting it if the destination is on a
+    /// different filesystem
+    Move,
+    Copy,
+    Hardlink,
+    /// Creates a symlink, at the destination, pointing to the file's absolute path
+    Symlink,
+    Delete,
+    /// Creates the destination directory, and any of its missing parents
+    Mkdir,
+    /// Sends an HTTP request about the file
+    Webhook,
+}
+
+/// What to do when the destination of a move, copy or link already exists
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OnConflict {
+    /// Leave the file, and the destination, as they are
+    Skip,
+    /// Replace the destination
+    Overwrite,
+    /// Use the first free destination with a numbered suffix, like `track (1).mp3`
+    Rename,
+    /// Replace the destination if the file was modified after it, otherwise skip the file
+    OverwriteIfNewer,
+    #[default]
+    Fail,
+    /// Skip the file if it has the same contents as the destination, deleting it if it was
+    /// being moved, otherwise rename it
+    DedupeIfIdentical,
+}
+
+/// What running an action did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// The destination already existed, and was left as it was
+    Skipped,
+    /// The destination already existed with the same contents as the file, and the file was
+    /// deleted if it was being moved
+    Deduplicated,
+}
+
+/// A change an action made to the filesystem, with enough detail to reverse it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Change {
+    Moved {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// A file, or link, was created
+    Created {
+        path: PathBuf,
+    },
+    CreatedDir {
+        path: PathBuf,
+    },
+    /// A file was deleted, or replaced, by moving it to the trash directory
+    Trashed {
+        path: PathBuf,
+        trash: PathBuf,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Moved { from, to } => {
+                write!(f, "the move of '{}' to '{}'", from.display(), to.display())
+            }
+            Change::Created { path } => write!(f, "the creation of '{}'", path.display()),
+            Change::CreatedDir { path } => {
+                write!(f, "the creation of the directory '{}'", path.display())
+            }
+            Change::Trashed { path, .. } => write!(f, "the deletion of '{}'", path.display()),
+        }
+    }
+}
+
+impl Operation {
+    /// Whether the operation needs a destination path
+    pub fn has_dest(&self) -> bool {
+        !matches!(self, Operation::Delete | Operation::Webhook)
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Operation::Move => "move",
+            Operation::Copy => "copy",
+            Operation::Hardlink => "hardlink",
+            Operation::Symlink => "symlink",
+            Operation::Delete => "delete",
+            Operation::Mkdir => "mkdir",
+            Operation::Webhook => "webhook",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Action<'a> {
+    operation: Operation,
+    source: &'a Path,
+    dest: Option<PathBuf>,
+    on_conflict: OnConflict,
+    trash: Option<PathBuf>,
+    webhook: Option<webhook::Webhook>,
+    journal: Option<(&'a journal::Journal, &'a str)>,
+}
+
+impl fmt::Display for Action<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.operation, &self.dest) {
+            (Operation::Webhook, _) => match &self.webhook {
+                Some(w) => write!(f, "{}", w),
+                None => write!(f, "webhook '{}'", self.source.display()),
+            },
+            (Operation::Delete, _) => write!(f, "delete '{}'", self.source.display()),
+            (Operation::Mkdir, Some(d)) => write!(f, "mkdir '{}'", d.display()),
+            (o, Some(d)) => write!(f, "{} '{}' to '{}'", o, self.source.display(), d.display()),
+            (o, None) => write!(f, "{} '{}'", o, self.source.display()),
+        }
+    }
+}
+
+impl<'a> Action<'a> {
+    pub fn new(operation: Operation, source: &'a Path, dest: Option<PathBuf>) -> Action<'a> {
+        Action {
+            operation,
+            source,
+            dest,
+            on_conflict: OnConflict::default(),
+            trash: None,
+            webhook: None,
+            journal: None,
+        }
+    }
+
+    /// Sets the request sent by the webhook action
+    pub fn webhook(mut self, webhook: Option<webhook::Webhook>) -> Action<'a> {
+        self.webhook = webhook;
+        self
+    }
+
+    /// Sets what to do if the destination of a move, copy or link already exists
+    pub fn on_conflict(mut self, on_conflict: OnConflict) -> Action<'a> {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// Sets the directory files are moved to, instead of being deleted, so that deleting or
+    /// replacing them can be reversed
+    pub fn trash(mut self, trash: Option<PathBuf>) -> Action<'a> {
+        self.trash = trash;
+        self
+    }
+
+    /// Sets the journal, and the library whose action this is, every change is recorded in as
+    /// soon as it's made, so that it can be reversed even if Librarian stops in the middle of
+    /// the action
+    pub fn journal(
+        mut self,
+        journal: Option<&'a journal::Journal>,
+        library: &'a str,
+    ) -> Action<'a> {
+        self.journal = journal.map(|j| (j, library));
+        self
+    }
+
+    /// Runs the operation, adding the changes it made to `changes` as it makes them. The
+    /// destination's parent directories are created if missing
+    pub fn run(&self, changes: &mut Vec<Change>) -> Result<Outcome, Error> {
+        let _running = shutdown::start_action().ok_or(Error::Stopped)?;
+        if self.operation == Operation::Webhook {
+            self.webhook.as_ref().ok_or(Error::MissingWebhook)?.send()?;
+            return Ok(Outcome::Done);
+        }
+        if self.operation == Operation::Delete {
+            self.remove(self.source, changes)?;
+            return Ok(Outcome::Done);
+        }
+
+        let dest = match &self.dest {
+            Some(d) => d.as_path(),
+            None => return Err(Error::MissingDestination(self.operation)),
+        };
+        if self.operation == Operation::Mkdir {
+            self.create_dir_all(dest, changes)?;
+            return Ok(Outcome::Done);
+        }
+        if let Some(parent) = dest.parent() {
+            self.create_dir_all(parent, changes)?;
+        }
+
+        // the destination is never replaced when it's written, so if another file was written
+        // to it after the conflict was resolved, the conflict is resolved again
+        loop {
+            let dest = match self.resolve_conflict(dest, changes)? {
+                Ok(d) => d,
+                Err(o) => return Ok(o),
+            };
+            let result = match self.operation {
+                Operation::Move => move_file(self.source, &dest),
+                Operation::Copy => copy_file(self.source, &dest),
+                Operation::Hardlink => fs::hard_link(self.source, &dest),
+                _ => symlink(self.source, &dest),
+            };
+            match result {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                r => self.io(r)?,
+            }
+
+            let change = match self.operation {
+                Operation::Move => Change::Moved {
+                    from: self.source.to_path_buf(),
+                    to: dest,
+                },
+                _ => Change::Created { path: dest },
+            };
+            self.push(changes, change)?;
+            return Ok(Outcome::Done);
+        }
+    }
+
+    // records the change in the journal, if there's one, then adds it to the changes
+    fn push(&self, changes: &mut Vec<Change>, change: Change) -> Result<(), Error> {
+        if let Some((j, library)) = self.journal {
+            j.record(library, change.clone())?;
+        }
+        changes.push(change);
+        Ok(())
+    }
+
+    // creates the directory and its missing parents, adding the ones that were created to changes
+    fn create_dir_all(&self, dir: &Path, changes: &mut Vec<Change>) -> Result<(), Error> {
+        for cur_dir in self.io(create_dir_all(dir))? {
+            self.push(changes, Change::CreatedDir { path: cur_dir })?;
+        }
+        Ok(())
+    }
+
+    // deletes the file, or moves it to the trash directory if there's one
+    fn remove(&self, path: &Path, changes: &mut Vec<Change>) -> Result<(), Error> {
+        let trash_dir = match &self.trash {
+            Some(t) => t,
+            None => return self.io(fs::remove_file(path)),
+        };
+
+        self.io(fs::create_dir_all(trash_dir))?;
+        // files with the same name can be trashed at the same time, so another free path is
+        // used if one was taken after it was picked
+        let trash = loop {
+            let trash = free_path(&trash_dir.join(path.file_name().unwrap_or_default()));
+            match move_file(path, &trash) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                r => self.io(r)?,
+            }
+            break trash;
+        };
+        self.push(
+            changes,
+            Change::Trashed {
+                path: path.to_path_buf(),
+                trash,
+            },
+        )
+    }
+
+    // returns the path the file should be written to if the destination already exists, or
+    // the outcome if the file shouldn't be written at all
+    fn resolve_conflict(
+        &self,
+        dest: &Path,
+        changes: &mut Vec<Change>,
+    ) -> Result<Result<PathBuf, Outcome>, Error> {
+        // symlink_metadata is used so that dangling symlinks are also considered conflicts
+        if fs::symlink_metadata(dest).is_err() {
+            return Ok(Ok(dest.to_path_buf()));
+        }
+        if is_same_file(self.source, dest) {
+            return Ok(Err(Outcome::Skipped));
+        }
+
+        match self.on_conflict {
+            OnConflict::Skip => Ok(Err(Outcome::Skipped)),
+            OnConflict::Overwrite => {
+                // removed rather than written over so that a symlink's target isn't modified
+                self.remove(dest, changes)?;
+                Ok(Ok(dest.to_path_buf()))
+            }
+            OnConflict::Rename => Ok(Ok(numbered_path(dest))),
+            OnConflict::OverwriteIfNewer => {
+                let source_modified = self.io(fs::metadata(self.source))?.modified();
+                let dest_modified = self.io(fs::metadata(dest))?.modified();
+                match (source_modified, dest_modified) {
+                    (Ok(s), Ok(d)) if s > d => {
+                        self.remove(dest, changes)?;
+                        Ok(Ok(dest.to_path_buf()))
+                    }
+                    _ => Ok(Err(Outcome::Skipped)),
+                }
+            }
+            OnConflict::Fail => Err(Error::Conflict(dest.to_path_buf())),
+            OnConflict::DedupeIfIdentical => {
+                if !self.is_identical(dest)? {
+                    return Ok(Ok(numbered_path(dest)));
+                }
+                if self.operation == Operation::Move {
+                    self.remove(self.source, changes)?;
+                }
+                Ok(Err(Outcome::Deduplicated))
+            }
+        }
+    }
+
+    fn is_identical(&self, dest: &Path) -> Result<bool, Error> {
+        let source_len = self.io(fs::metadata(self.source))?.len();
+        let dest_metadata = self.io(fs::metadata(dest))?;
+        if !dest_metadata.is_file() || dest_metadata.len() != source_len {
+            return Ok(false);
+        }
+
+        let algorithm = [checksum::Algorithm::Blake3];
+        let source_checksums = checksum::File::new(self.source).get_checksums(&algorithm)?;
+        let dest_checksums = checksum::File::new(dest).get_checksums(&algorithm)?;
+        Ok(source_checksums == dest_checksums)
+    }
+
+    fn io<T>(&self, result: io::Result<T>) -> Result<T, Error> {
+        result.map_err(|e| Error::Io(self.to_string(), e))
+    }
+}
+
+fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+// creates the directory and its missing parents, returning the ones that were created, parents
+// first
+fn create_dir_all(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut missing = Vec::new();
+    let mut cur_dir = Some(dir);
+    while let Some(d) = cur_dir {
+        if d.as_os_str().is_empty() || fs::symlink_metadata(d).is_ok() {
+            break;
+        }
+        missing.push(d);
+        cur_dir = d.parent();
+    }
+
+    fs::create_dir_all(dir)?;
+    Ok(missing.into_iter().rev().map(|d| d.to_path_buf()).collect())
+}
+
+// returns the path if it doesn't exist, otherwise the first free path with a numbered suffix
+pub fn free_path(path: &Path) -> PathBuf {
+    if fs::symlink_metadata(path).is_err() {
+        return path.to_path_buf();
+    }
+
+    numbered_path(path)
+}
+
+// returns the first path, with a numbered suffix added to the file's name, that doesn't exist
+fn numbered_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    let mut i: u64 = 1;
+    loop {
+        let candidate = path.with_file_name(format!("{} ({}){}", stem, i, extension));
+        if fs::symlink_metadata(&candidate).is_err() {
+            return candidate;
+        }
+        i += 1;
+    }
+}
+
+/// Moves the file without replacing the destination, failing with `AlreadyExists` if it
+/// exists. The file is linked to the destination, then unlinked, or copied then deleted if the
+/// destination is on a different filesystem, or one without hard links
+pub fn move_file(source: &Path, dest: &Path) -> io::Result<()> {
+    match fs::hard_link(source, dest) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+        Err(_) => copy_file(source, dest)?,
+    }
+
+    fs::remove_file(source)
+}
+
+// copies the file, and its permissions, without replacing the destination. A partially written
+// destination is removed
+fn copy_file(source: &Path, dest: &Path) -> io::Result<()> {
+    let mut reader = fs::File::open(source)?;
+    let permissions = reader.metadata()?.permissions();
+    let mut writer = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(dest)?;
+
+    let result = io::copy(&mut reader, &mut writer)
+        .and_then(|_| writer.sync_all())
+        .and_then(|_| fs::set_permissions(dest, permissions));
+    if result.is_err() {
+        let _ = fs::remove_file(dest);
+    }
+    result
+}
+
+fn symlink(source: &Path, dest: &Path) -> io::Result<()> {
+    let source = fs::canonicalize(source)?;
+
+    #[cfg(target_family = "unix")]
+    return std::os::unix::fs::symlink(source, dest);
+    #[cfg(target_family = "windows")]
+    return std::os::windows::fs::symlink_file(source, dest);
+}
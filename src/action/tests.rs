@@ -0,0 +1,289 @@
+use super::*;
+
+fn tmp_path(name: &str) -> PathBuf {
+    ["tests", "tmp", "action", name].iter().collect()
+}
+
+fn create_file(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn test_run() {
+    let source = tmp_path("copy_source");
+    create_file(&source, "copied");
+    let dest = tmp_path("copy/nested/dest");
+    let _ = fs::remove_dir_all(tmp_path("copy"));
+    Action::new(Operation::Copy, &source, Some(dest.clone()))
+        .run(&mut Vec::new())
+        .unwrap();
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "copied");
+    assert!(source.exists());
+
+    let dest = tmp_path("move/dest");
+    let _ = fs::remove_dir_all(tmp_path("move"));
+    Action::new(Operation::Move, &source, Some(dest.clone()))
+        .run(&mut Vec::new())
+        .unwrap();
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "copied");
+    assert!(!source.exists());
+
+    let link = tmp_path("hardlink");
+    let _ = fs::remove_file(&link);
+    Action::new(Operation::Hardlink, &dest, Some(link.clone()))
+        .run(&mut Vec::new())
+        .unwrap();
+    assert_eq!(fs::read_to_string(&link).unwrap(), "copied");
+
+    let link = tmp_path("symlink");
+    let _ = fs::remove_file(&link);
+    Action::new(Operation::Symlink, &dest, Some(link.clone()))
+        .run(&mut Vec::new())
+        .unwrap();
+    assert!(fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(
+        fs::read_link(&link).unwrap(),
+        fs::canonicalize(&dest).unwrap()
+    );
+
+    Action::new(Operation::Delete, &dest, None)
+        .run(&mut Vec::new())
+        .unwrap();
+    assert!(!dest.exists());
+
+    let dir = tmp_path("mkdir/nested");
+    let _ = fs::remove_dir_all(tmp_path("mkdir"));
+    Action::new(Operation::Mkdir, &source, Some(dir.clone()))
+        .run(&mut Vec::new())
+        .unwrap();
+    assert!(dir.is_dir());
+}
+
+#[test]
+fn test_run_errors() {
+    let source = tmp_path("missing_source");
+    let err = Action::new(Operation::Copy, &source, Some(tmp_path("missing_dest")))
+        .run(&mut Vec::new())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "An IO error was thrown while trying to copy '{}' to '{}'",
+            source.display(),
+            tmp_path("missing_dest").display()
+        )
+    );
+
+    let err = Action::new(Operation::Move, &source, None)
+        .run(&mut Vec::new())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "The move action requires a destination");
+}
+
+#[test]
+fn test_run_on_conflict() {
+    let dir = tmp_path("conflict");
+    let _ = fs::remove_dir_all(&dir);
+    let source = dir.join("source.txt");
+    let dest = dir.join("dest.txt");
+    create_file(&source, "new");
+    create_file(&dest, "old");
+
+    let copy = |on_conflict| {
+        Action::new(Operation::Copy, &source, Some(dest.clone()))
+            .on_conflict(on_conflict)
+            .run(&mut Vec::new())
+    };
+    assert!(matches!(copy(OnConflict::Fail), Err(Error::Conflict(_))));
+    assert_eq!(copy(OnConflict::Skip).unwrap(), Outcome::Skipped);
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+
+    assert_eq!(copy(OnConflict::Rename).unwrap(), Outcome::Done);
+    assert_eq!(copy(OnConflict::Rename).unwrap(), Outcome::Done);
+    assert_eq!(fs::read_to_string(dir.join("dest (1).txt")).unwrap(), "new");
+    assert_eq!(fs::read_to_string(dir.join("dest (2).txt")).unwrap(), "new");
+
+    // the destination was written after the source
+    assert_eq!(
+        copy(OnConflict::OverwriteIfNewer).unwrap(),
+        Outcome::Skipped
+    );
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+
+    assert_eq!(copy(OnConflict::Overwrite).unwrap(), Outcome::Done);
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+
+    assert_eq!(
+        Action::new(Operation::Move, &source, Some(dest.clone()))
+            .on_conflict(OnConflict::DedupeIfIdentical)
+            .run(&mut Vec::new())
+            .unwrap(),
+        Outcome::Deduplicated
+    );
+    assert!(!source.exists());
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+
+    create_file(&source, "different");
+    assert_eq!(copy(OnConflict::DedupeIfIdentical).unwrap(), Outcome::Done);
+    assert_eq!(
+        fs::read_to_string(dir.join("dest (3).txt")).unwrap(),
+        "different"
+    );
+}
+
+#[test]
+fn test_run_concurrent_conflicts() {
+    let dir = tmp_path("concurrent");
+    let _ = fs::remove_dir_all(&dir);
+    let sources: Vec<PathBuf> = (0..8)
+        .map(|i| dir.join(format!("{}/track.mp3", i)))
+        .collect();
+    for (i, cur_source) in sources.iter().enumerate() {
+        create_file(cur_source, &i.to_string());
+    }
+
+    // every file is moved to the same destination at the same time, and none of them should
+    // replace another
+    let dest = dir.join("music/track.mp3");
+    let outcomes: Vec<Result<Outcome, Error>> = std::thread::scope(|s| {
+        let handles: Vec<_> = sources
+            .iter()
+            .map(|cur_source| {
+                let dest = dest.clone();
+                s.spawn(move || {
+                    Action::new(Operation::Move, cur_source, Some(dest))
+                        .on_conflict(OnConflict::Rename)
+                        .run(&mut Vec::new())
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert!(outcomes.iter().all(|o| matches!(o, Ok(Outcome::Done))));
+    let mut contents: Vec<String> = fs::read_dir(dir.join("music"))
+        .unwrap()
+        .map(|e| fs::read_to_string(e.unwrap().path()).unwrap())
+        .collect();
+    contents.sort();
+    assert_eq!(contents, (0..8).map(|i| i.to_string()).collect::<Vec<_>>());
+
+    // only one of the files can be copied to a destination that doesn't exist yet
+    let dest = dir.join("copies/track.mp3");
+    let sources: Vec<PathBuf> = fs::read_dir(dir.join("music"))
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    let done = std::thread::scope(|s| {
+        let handles: Vec<_> = sources
+            .iter()
+            .map(|cur_source| {
+                let dest = dest.clone();
+                s.spawn(move || {
+                    Action::new(Operation::Copy, cur_source, Some(dest)).run(&mut Vec::new())
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .filter(|o| o.is_ok())
+            .count()
+    });
+    assert_eq!(done, 1);
+}
+
+#[test]
+fn test_run_concurrent_trash() {
+    let dir = tmp_path("concurrent_trash");
+    let _ = fs::remove_dir_all(&dir);
+    let trash = dir.join("trash");
+    let files: Vec<PathBuf> = (0..8)
+        .map(|i| dir.join(format!("{}/cover.jpg", i)))
+        .collect();
+    for (i, cur_file) in files.iter().enumerate() {
+        create_file(cur_file, &i.to_string());
+    }
+
+    // files with the same name deleted at the same time should all be kept in the trash
+    std::thread::scope(|s| {
+        for cur_file in files.iter() {
+            let trash = trash.clone();
+            s.spawn(move || {
+                Action::new(Operation::Delete, cur_file, None)
+                    .trash(Some(trash))
+                    .run(&mut Vec::new())
+                    .unwrap()
+            });
+        }
+    });
+    let mut contents: Vec<String> = fs::read_dir(&trash)
+        .unwrap()
+        .map(|e| fs::read_to_string(e.unwrap().path()).unwrap())
+        .collect();
+    contents.sort();
+    assert_eq!(contents, (0..8).map(|i| i.to_string()).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_run_changes() {
+    let dir = tmp_path("changes");
+    let _ = fs::remove_dir_all(&dir);
+    let trash = dir.join("trash");
+    let source = dir.join("source.txt");
+    let dest = dir.join("nested/dest.txt");
+    create_file(&source, "new");
+
+    let mut changes = Vec::new();
+    Action::new(Operation::Copy, &source, Some(dest.clone()))
+        .trash(Some(trash.clone()))
+        .run(&mut changes)
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            Change::CreatedDir {
+                path: dir.join("nested")
+            },
+            Change::Created { path: dest.clone() },
+        ]
+    );
+
+    let mut changes = Vec::new();
+    Action::new(Operation::Move, &source, Some(dest.clone()))
+        .on_conflict(OnConflict::Overwrite)
+        .trash(Some(trash.clone()))
+        .run(&mut changes)
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            Change::Trashed {
+                path: dest.clone(),
+                trash: trash.join("dest.txt")
+            },
+            Change::Moved {
+                from: source.clone(),
+                to: dest.clone()
+            },
+        ]
+    );
+    assert_eq!(fs::read_to_string(trash.join("dest.txt")).unwrap(), "new");
+
+    let mut changes = Vec::new();
+    Action::new(Operation::Delete, &dest, None)
+        .trash(Some(trash.clone()))
+        .run(&mut changes)
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![Change::Trashed {
+            path: dest.clone(),
+            trash: trash.join("dest (1).txt")
+        }]
+    );
+    assert!(!dest.exists());
+}
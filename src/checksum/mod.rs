@@ -0,0 +1,116 @@
+use md5::Digest;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+#[cfg(test)]
+mod tests;
+
+const READ_BUFFER_BYTES: usize = 65536;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("An IO error was thrown while trying to calculate the checksum of a file")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+    Md5,
+}
+
+pub const ALGORITHMS: [Algorithm; 3] = [Algorithm::Sha256, Algorithm::Blake3, Algorithm::Md5];
+
+impl Algorithm {
+    /// The name of the algorithm, which is also the name of the template variable holding the
+    /// file's checksum
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Blake3 => "blake3",
+            Algorithm::Md5 => "md5",
+        }
+    }
+}
+
+enum Hasher {
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Md5(md5::Md5),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Hasher {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            Algorithm::Md5 => Hasher::Md5(md5::Md5::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+            Hasher::Md5(h) => h.update(data),
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(h) => to_hex(h.finalize().as_slice()),
+            Hasher::Blake3(h) => h.finalize().to_hex().to_string(),
+            Hasher::Md5(h) => to_hex(h.finalize().as_slice()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct File<'a> {
+    path: &'a Path,
+}
+
+impl<'a> File<'a> {
+    pub fn new(path: &'a Path) -> File<'a> {
+        File { path }
+    }
+
+    /// Calculates the file's checksums for all the provided algorithms, reading the file once
+    pub fn get_checksums(
+        &self,
+        algorithms: &[Algorithm],
+    ) -> Result<HashMap<Algorithm, String>, Error> {
+        let mut hashers: Vec<(Algorithm, Hasher)> =
+            algorithms.iter().map(|a| (*a, Hasher::new(*a))).collect();
+        if hashers.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut file_obj = std::fs::File::open(self.path)?;
+        let mut buf = vec![0u8; READ_BUFFER_BYTES];
+        loop {
+            let num_read = file_obj.read(&mut buf)?;
+            if num_read == 0 {
+                break;
+            }
+            for (_, cur_hasher) in hashers.iter_mut() {
+                cur_hasher.update(&buf[..num_read]);
+            }
+        }
+
+        Ok(hashers
+            .into_iter()
+            .map(|(algorithm, hasher)| (algorithm, hasher.finalize()))
+            .collect())
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
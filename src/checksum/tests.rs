@@ -0,0 +1,28 @@
+use super::*;
+
+#[test]
+fn test_get_checksums() {
+    let checksums = File::new(Path::new("tests/files/text/plain"))
+        .get_checksums(&ALGORITHMS)
+        .unwrap();
+    assert_eq!(
+        checksums[&Algorithm::Sha256],
+        "847c439abd3eda79b01e4fcfbdc7ebc50a48ac78f7e4f6777a90a1eda091a986"
+    );
+    assert_eq!(
+        checksums[&Algorithm::Md5],
+        "f475529ca97ec7cf784c39975fc631b2"
+    );
+    assert_eq!(checksums[&Algorithm::Blake3].len(), 64);
+
+    let checksums = File::new(Path::new("tests/files/text/plain"))
+        .get_checksums(&[Algorithm::Md5])
+        .unwrap();
+    assert_eq!(checksums.len(), 1);
+
+    assert!(File::new(Path::new("tests/files/unavialabile_file"))
+        .get_checksums(&[Algorithm::Sha256])
+        .unwrap_err()
+        .to_string()
+        .contains("An IO error was thrown while trying to calculate the checksum of a file"));
+}
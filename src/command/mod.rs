@@ -0,0 +1,598 @@
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests;
+
+// how often to check whether a command with a timeout has exited
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// IDs of the processes running commands, which on unix are also the IDs of their process groups
+static RUNNING: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+// set once Librarian starts shutting down, after which no new commands are started
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("An IO error was thrown while trying to run a command")]
+    Io(#[from] std::io::Error),
+    #[error("The shell used to run a command can't be empty")]
+    EmptyShell,
+    #[error("The command wasn't started since Librarian is shutting down")]
+    Stopped,
+    #[error("The user {0}, that the command should run as, doesn't exist")]
+    #[cfg(target_family = "unix")]
+    UnknownUser(String),
+    #[error("The group {0}, that the command should run as, doesn't exist")]
+    #[cfg(target_family = "unix")]
+    UnknownGroup(String),
+    #[error("An error was thrown while trying to look up {0}, that the command should run as")]
+    #[cfg(target_family = "unix")]
+    Lookup(String, #[source] nix::Error),
+    #[cfg(not(target_family = "unix"))]
+    #[error("Running commands as a different user isn't supported on this OS")]
+    RunAsUnsupported,
+    #[error("An error was thrown while trying to set up the command's sandbox")]
+    #[cfg(target_os = "linux")]
+    Sandbox(#[from] landlock::RulesetError),
+    #[cfg(not(target_os = "linux"))]
+    #[error("Sandboxing commands isn't supported on this OS")]
+    SandboxUnsupported,
+    #[error("An error was thrown while trying to kill a command's process group")]
+    #[cfg(target_family = "unix")]
+    Kill(#[from] nix::Error),
+}
+
+/// The result of running a command to completion
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Output {
+    /// The command's exit code. Is `None` if the command was terminated by a signal
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub duration: Duration,
+    /// Whether the command was killed because it ran for longer than its timeout
+    pub timed_out: bool,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        !self.timed_out && self.exit_code == Some(0)
+    }
+}
+
+/// What the command reads from its standard input
+#[derive(Debug, Default, Clone)]
+pub enum Stdin {
+    #[default]
+    Null,
+    /// The contents of the file, streamed to the command
+    File(PathBuf),
+    Data(Vec<u8>),
+}
+
+/// The IO scheduling class of a command, as set by `ionice`
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IoniceClass {
+    Realtime,
+    BestEffort,
+    /// Only gets disk time when no other process needs it
+    Idle,
+}
+
+/// Limits applied to the command's process, and any process it starts, before the command
+/// runs. Only applied on Linux
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Limits {
+    pub nice: Option<i32>,
+    pub ionice: Option<IoniceClass>,
+    /// The maximum number of seconds of CPU time
+    pub cpu_seconds: Option<u64>,
+    /// The maximum size, in bytes, of the process's virtual memory
+    pub address_space: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+/// The only paths the command's process, and any process it starts, can access, enforced with
+/// Landlock. Only supported on Linux
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Sandbox {
+    pub read_only: Vec<PathBuf>,
+    pub read_write: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct Command {
+    cmd: String,
+    timeout: Option<Duration>,
+    shell: Option<Vec<String>>,
+    env: HashMap<String, String>,
+    clear_env: bool,
+    cwd: Option<PathBuf>,
+    stdin: Stdin,
+    limits: Limits,
+    run_as: Option<String>,
+    sandbox: Option<Sandbox>,
+}
+
+impl Command {
+    pub fn new(cmd: String) -> Command {
+        Command {
+            cmd,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the program, and its arguments, that the command is passed to as the last
+    /// argument. Defaults to `sh -c`, or `cmd /C` on Windows
+    pub fn shell(mut self, shell: Option<Vec<String>>) -> Command {
+        self.shell = shell;
+        self
+    }
+
+    /// Sets environment variables for the command, in addition to the ones it inherits
+    pub fn env(mut self, env: HashMap<String, String>) -> Command {
+        self.env = env;
+        self
+    }
+
+    /// Sets whether the command should not inherit Librarian's environment variables
+    pub fn clear_env(mut self, clear_env: bool) -> Command {
+        self.clear_env = clear_env;
+        self
+    }
+
+    /// Sets the directory the command runs in. Defaults to Librarian's working directory
+    pub fn cwd(mut self, cwd: Option<PathBuf>) -> Command {
+        self.cwd = cwd;
+        self
+    }
+
+    /// Sets what the command reads from its standard input. Defaults to nothing
+    pub fn stdin(mut self, stdin: Stdin) -> Command {
+        self.stdin = stdin;
+        self
+    }
+
+    /// Sets the niceness, IO scheduling class and resource limits of the command
+    pub fn limits(mut self, limits: Limits) -> Command {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the paths, and the files and directories under them, the command can access. Missing
+    /// paths are ignored
+    pub fn sandbox(mut self, sandbox: Option<Sandbox>) -> Command {
+        self.sandbox = sandbox;
+        self
+    }
+
+    /// Sets the user, and optionally the group, as `user:group`, the command runs as. Users and
+    /// groups can be names or IDs. Defaults to the user's primary group
+    pub fn run_as(mut self, run_as: Option<String>) -> Command {
+        self.run_as = run_as;
+        self
+    }
+
+    /// Sets the maximum amount of time the command is allowed to run for, after which the
+    /// command, and any process it started, is killed
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Command {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Runs the command using the OS's shell, waiting for it to exit
+    pub fn run(&self) -> Result<Output, Error> {
+        let start = Instant::now();
+        let mut command = self.build()?;
+        let stdin = match self.stdin {
+            Stdin::Null => process::Stdio::null(),
+            Stdin::File(_) | Stdin::Data(_) => process::Stdio::piped(),
+        };
+        command
+            .stdin(stdin)
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped());
+        // run the command in its own process group so that it, and any processes it starts,
+        // can be killed together
+        #[cfg(target_family = "unix")]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+        // opened before the command is started so that failing to open it doesn't leave the
+        // command running
+        let stdin_source = self.stdin_source()?;
+
+        let mut child = {
+            let mut running = lock_running();
+            if STOPPED.load(Ordering::SeqCst) {
+                return Err(Error::Stopped);
+            }
+            let child = command.spawn()?;
+            running.push(child.id());
+            child
+        };
+        let stdin_writer = write_stdin_in_thread(child.stdin.take(), stdin_source);
+        let stdout_reader = read_to_string_in_thread(child.stdout.take());
+        let stderr_reader = read_to_string_in_thread(child.stderr.take());
+        let wait_result = self.wait(&mut child);
+        lock_running().retain(|p| *p != child.id());
+        let (status, timed_out) = wait_result?;
+        if let Some(w) = stdin_writer {
+            let _ = w.join();
+        }
+
+        Ok(Output {
+            exit_code: status.code(),
+            stdout: stdout_reader.join().unwrap_or_default(),
+            stderr: stderr_reader.join().unwrap_or_default(),
+            duration: start.elapsed(),
+            timed_out,
+        })
+    }
+
+    fn build(&self) -> Result<process::Command, Error> {
+        let default_shell = if env::consts::OS == "windows" {
+            ["cmd", "/C"]
+        } else {
+            ["sh", "-c"]
+        };
+        let shell: Vec<&str> = match &self.shell {
+            Some(s) => s.iter().map(|a| a.as_str()).collect(),
+            None => default_shell.to_vec(),
+        };
+        let (program, args) = match shell.split_first() {
+            Some(s) => s,
+            None => return Err(Error::EmptyShell),
+        };
+
+        let mut command = process::Command::new(program);
+        command.args(args).arg(&self.cmd);
+        if self.clear_env {
+            command.env_clear();
+        }
+        command.envs(self.env.iter());
+        if let Some(c) = &self.cwd {
+            command.current_dir(c);
+        }
+        #[cfg(target_family = "unix")]
+        {
+            let limits = self.limits.clone();
+            let credentials = match &self.run_as {
+                Some(r) => Some(Credentials::new(r)?),
+                None => None,
+            };
+            if let Some(u) = credentials.as_ref().and_then(|c| c.user.as_ref()) {
+                for (cur_key, cur_val) in [
+                    ("HOME", u.dir.as_os_str()),
+                    ("USER", u.name.as_ref()),
+                    ("LOGNAME", u.name.as_ref()),
+                ] {
+                    if !self.env.contains_key(cur_key) {
+                        command.env(cur_key, cur_val);
+                    }
+                }
+            }
+
+            #[cfg(target_os = "linux")]
+            let mut ruleset = match &self.sandbox {
+                Some(s) => Some(sandbox_ruleset(s)?),
+                None => None,
+            };
+
+            if limits != Limits::default() || credentials.is_some() || self.sandbox.is_some() {
+                // only async-signal-safe functions are called between forking and running the
+                // command. The limits are applied first since lowering the niceness requires
+                // the privileges that running as a different user drops
+                unsafe {
+                    std::os::unix::process::CommandExt::pre_exec(&mut command, move || {
+                        #[cfg(target_os = "linux")]
+                        apply_limits(&limits)?;
+                        if let Some(c) = &credentials {
+                            c.apply()?;
+                        }
+                        #[cfg(target_os = "linux")]
+                        if let Some(r) = ruleset.take() {
+                            restrict_self(r)?;
+                        }
+                        Ok(())
+                    });
+                }
+            }
+        }
+        #[cfg(not(target_family = "unix"))]
+        if self.run_as.is_some() {
+            return Err(Error::RunAsUnsupported);
+        }
+        #[cfg(not(target_os = "linux"))]
+        if self.sandbox.is_some() {
+            return Err(Error::SandboxUnsupported);
+        }
+
+        Ok(command)
+    }
+
+    // returns what should be written to the command's standard input, if anything
+    fn stdin_source(&self) -> Result<Option<Box<dyn Read + Send>>, Error> {
+        Ok(match &self.stdin {
+            Stdin::Null => None,
+            Stdin::File(p) => Some(Box::new(fs::File::open(p)?)),
+            Stdin::Data(d) => Some(Box::new(io::Cursor::new(d.clone()))),
+        })
+    }
+
+    // waits for the child to exit, killing it if it runs past the timeout. Returns the child's
+    // exit status and whether it was killed because of the timeout
+    fn wait(&self, child: &mut process::Child) -> Result<(process::ExitStatus, bool), Error> {
+        let deadline = match self.timeout {
+            Some(t) => Instant::now() + t,
+            None => return Ok((child.wait()?, false)),
+        };
+
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok((status, false));
+            }
+
+            if Instant::now() >= deadline {
+                kill(child)?;
+                return Ok((child.wait()?, true));
+            }
+
+            thread::sleep(WAIT_POLL_INTERVAL);
+        }
+    }
+}
+
+// the user, group and supplementary groups a command runs as, resolved before forking since
+// reading the user and group databases isn't safe to do between forking and running a command
+#[cfg(target_family = "unix")]
+#[derive(Debug)]
+struct Credentials {
+    uid: nix::unistd::Uid,
+    gid: nix::unistd::Gid,
+    groups: Vec<nix::unistd::Gid>,
+    user: Option<nix::unistd::User>,
+}
+
+#[cfg(target_family = "unix")]
+impl Credentials {
+    fn new(run_as: &str) -> Result<Credentials, Error> {
+        use nix::unistd::{Gid, Group, Uid, User};
+
+        let (user_str, group_str) = match run_as.split_once(':') {
+            Some((u, g)) => (u, Some(g)),
+            None => (run_as, None),
+        };
+        let unknown_user = || Error::UnknownUser(user_str.to_string());
+        fn lookup(name: &str) -> impl FnOnce(nix::Error) -> Error + '_ {
+            move |e| Error::Lookup(name.to_string(), e)
+        }
+        let user = match user_str.parse::<u32>() {
+            Ok(id) => User::from_uid(Uid::from_raw(id)).map_err(lookup(user_str))?,
+            Err(_) => Some(
+                User::from_name(user_str)
+                    .map_err(lookup(user_str))?
+                    .ok_or_else(unknown_user)?,
+            ),
+        };
+        let uid = match (&user, user_str.parse::<u32>()) {
+            (Some(u), _) => u.uid,
+            (None, Ok(id)) => Uid::from_raw(id),
+            (None, Err(_)) => return Err(unknown_user()),
+        };
+
+        let gid = match group_str {
+            Some(g) => match g.parse::<u32>() {
+                Ok(id) => Gid::from_raw(id),
+                Err(_) => {
+                    Group::from_name(g)
+                        .map_err(lookup(g))?
+                        .ok_or_else(|| Error::UnknownGroup(g.to_string()))?
+                        .gid
+                }
+            },
+            None => user.as_ref().ok_or_else(unknown_user)?.gid,
+        };
+
+        let mut groups = vec![gid];
+        #[cfg(target_os = "linux")]
+        if let Some(u) = &user {
+            if let Ok(name) = std::ffi::CString::new(u.name.as_str()) {
+                groups = nix::unistd::getgrouplist(&name, gid).map_err(lookup(user_str))?;
+            }
+        }
+
+        Ok(Credentials {
+            uid,
+            gid,
+            groups,
+            user,
+        })
+    }
+
+    // drops the privileges of the current process, supplementary groups first, since changing
+    // the user makes it impossible to change the groups
+    fn apply(&self) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        nix::unistd::setgroups(&self.groups)?;
+        nix::unistd::setgid(self.gid)?;
+        nix::unistd::setuid(self.uid)?;
+        Ok(())
+    }
+}
+
+// creates the Landlock ruleset, and opens the sandbox's paths, before forking since neither is
+// safe to do between forking and running a command. Access rights the running kernel doesn't
+// support are left out
+#[cfg(target_os = "linux")]
+fn sandbox_ruleset(sandbox: &Sandbox) -> Result<landlock::RulesetCreated, Error> {
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, ABI,
+    };
+
+    let abi = ABI::V6;
+    Ok(Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))?
+        .create()?
+        .add_rules(path_beneath_rules(
+            &sandbox.read_only,
+            AccessFs::from_read(abi),
+        ))?
+        .add_rules(path_beneath_rules(
+            &sandbox.read_write,
+            AccessFs::from_all(abi),
+        ))?)
+}
+
+// fails if the kernel doesn't support Landlock, instead of running the command without a
+// sandbox
+#[cfg(target_os = "linux")]
+fn restrict_self(ruleset: landlock::RulesetCreated) -> io::Result<()> {
+    match ruleset.restrict_self() {
+        Ok(s) if s.ruleset != landlock::RulesetStatus::NotEnforced => Ok(()),
+        Ok(_) => Err(io::Error::from(io::ErrorKind::Unsupported)),
+        Err(_) => Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn apply_limits(limits: &Limits) -> io::Result<()> {
+    use nix::libc;
+
+    // the IO priority used by the kernel, within the best-effort and realtime classes, for
+    // processes that don't set one
+    const IOPRIO_DEFAULT_LEVEL: libc::c_int = 4;
+    const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+    const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+    if let Some(n) = limits.nice {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, n) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    if let Some(c) = limits.ionice {
+        let ioprio = match c {
+            IoniceClass::Realtime => (1 << IOPRIO_CLASS_SHIFT) | IOPRIO_DEFAULT_LEVEL,
+            IoniceClass::BestEffort => (2 << IOPRIO_CLASS_SHIFT) | IOPRIO_DEFAULT_LEVEL,
+            IoniceClass::Idle => 3 << IOPRIO_CLASS_SHIFT,
+        };
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    for (cur_resource, cur_limit) in [
+        (libc::RLIMIT_CPU, limits.cpu_seconds),
+        (libc::RLIMIT_AS, limits.address_space),
+        (libc::RLIMIT_NOFILE, limits.open_files),
+    ] {
+        let limit = match cur_limit {
+            Some(l) => l as libc::rlim_t,
+            None => continue,
+        };
+        let rlimit = libc::rlimit {
+            rlim_cur: limit,
+            rlim_max: limit,
+        };
+        if unsafe { libc::setrlimit(cur_resource, &rlimit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+/// Stops new commands from being started
+pub fn stop() {
+    let _running = lock_running();
+    STOPPED.store(true, Ordering::SeqCst);
+}
+
+/// Returns the number of commands that are running
+pub fn running() -> usize {
+    lock_running().len()
+}
+
+/// Kills all the commands that are running, and any processes they started
+pub fn kill_running() {
+    for cur_pid in lock_running().iter() {
+        if let Err(e) = kill_pid(*cur_pid) {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+fn lock_running() -> std::sync::MutexGuard<'static, Vec<u32>> {
+    match RUNNING.lock() {
+        Ok(r) => r,
+        Err(p) => p.into_inner(),
+    }
+}
+
+fn kill(child: &mut process::Child) -> Result<(), Error> {
+    kill_pid(child.id())
+}
+
+#[cfg(target_family = "unix")]
+fn kill_pid(pid: u32) -> Result<(), Error> {
+    use nix::sys::signal::{killpg, Signal};
+    use nix::unistd::Pid;
+
+    match killpg(Pid::from_raw(pid as i32), Signal::SIGKILL) {
+        // the command, and every process it started, already exited
+        Ok(()) | Err(nix::errno::Errno::ESRCH) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+fn kill_pid(pid: u32) -> Result<(), Error> {
+    process::Command::new("taskkill")
+        .args(["/F", "/T", "/PID", &pid.to_string()])
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .status()?;
+    Ok(())
+}
+
+// writes the command's standard input in a separate thread so that a command that doesn't read
+// all of it, or writes a lot of output before reading it, doesn't block Librarian
+fn write_stdin_in_thread(
+    stdin: Option<process::ChildStdin>,
+    source: Option<Box<dyn Read + Send>>,
+) -> Option<thread::JoinHandle<()>> {
+    let (mut stdin, mut source) = match (stdin, source) {
+        (Some(i), Some(s)) => (i, s),
+        _ => return None,
+    };
+
+    Some(thread::spawn(move || {
+        // the command closing its standard input early isn't an error
+        let _ = io::copy(&mut source, &mut stdin);
+        let _ = stdin.flush();
+    }))
+}
+
+fn read_to_string_in_thread<R: Read + Send + 'static>(
+    reader: Option<R>,
+) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut r) = reader {
+            let _ = r.read_to_end(&mut buf);
+        }
+
+        String::from_utf8_lossy(&buf).to_string()
+    })
+}
@@ -0,0 +1,187 @@
+use super::*;
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_run() {
+    let output = Command::new("echo hello; echo world >&2".to_string())
+        .run()
+        .unwrap();
+    assert!(output.success());
+    assert_eq!(output.exit_code, Some(0));
+    assert_eq!(output.stdout, "hello\n");
+    assert_eq!(output.stderr, "world\n");
+
+    let output = Command::new("echo failed >&2; exit 3".to_string())
+        .run()
+        .unwrap();
+    assert!(!output.success());
+    assert_eq!(output.exit_code, Some(3));
+    assert_eq!(output.stderr, "failed\n");
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_run_timeout() {
+    let start = Instant::now();
+    // the background sleep is in the same process group and should be killed too, otherwise
+    // reading its inherited stdout would block until it exits
+    let output = Command::new("sleep 30 & echo started; sleep 30".to_string())
+        .timeout(Some(Duration::from_millis(200)))
+        .run()
+        .unwrap();
+    assert!(output.timed_out);
+    assert!(!output.success());
+    assert_eq!(output.exit_code, None);
+    assert_eq!(output.stdout, "started\n");
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    let output = Command::new("echo done".to_string())
+        .timeout(Some(Duration::from_secs(10)))
+        .run()
+        .unwrap();
+    assert!(!output.timed_out);
+    assert!(output.success());
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_run_environment() {
+    let env: HashMap<String, String> = [("LIBRARIAN_TEST_VAR".to_string(), "set".to_string())]
+        .iter()
+        .cloned()
+        .collect();
+    let output = Command::new("echo \"$LIBRARIAN_TEST_VAR $HOME\"; pwd".to_string())
+        .env(env.clone())
+        .clear_env(true)
+        .cwd(Some(PathBuf::from("tests/files")))
+        .run()
+        .unwrap();
+    let mut lines = output.stdout.lines();
+    assert_eq!(lines.next().unwrap().trim_end(), "set");
+    assert!(lines.next().unwrap().ends_with("tests/files"));
+
+    let output = Command::new("echo $((2 + 3))".to_string())
+        .shell(Some(vec![
+            "env".to_string(),
+            "sh".to_string(),
+            "-c".to_string(),
+        ]))
+        .run()
+        .unwrap();
+    assert_eq!(output.stdout, "5\n");
+
+    assert!(Command::new("true".to_string())
+        .shell(Some(vec![]))
+        .run()
+        .unwrap_err()
+        .to_string()
+        .contains("can't be empty"));
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_run_stdin() {
+    let output = Command::new("cat".to_string())
+        .stdin(Stdin::Data(b"piped data".to_vec()))
+        .run()
+        .unwrap();
+    assert_eq!(output.stdout, "piped data");
+
+    let path: PathBuf = ["tests", "files", "text", "plain"].iter().collect();
+    let output = Command::new("wc -c".to_string())
+        .stdin(Stdin::File(path.clone()))
+        .run()
+        .unwrap();
+    assert_eq!(
+        output.stdout.trim().parse::<u64>().unwrap(),
+        fs::metadata(&path).unwrap().len()
+    );
+
+    // a command that doesn't read its standard input shouldn't block
+    let output = Command::new("true".to_string())
+        .stdin(Stdin::Data(vec![0; 1 << 20]))
+        .run()
+        .unwrap();
+    assert!(output.success());
+
+    let output = Command::new("cat".to_string()).run().unwrap();
+    assert_eq!(output.stdout, "");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_run_limits() {
+    let limits = Limits {
+        nice: Some(19),
+        ionice: Some(IoniceClass::Idle),
+        cpu_seconds: Some(120),
+        address_space: None,
+        open_files: Some(64),
+    };
+    let output = Command::new(
+        "echo $(cut -d ' ' -f 19 /proc/self/stat) $(ulimit -t) $(ulimit -n)".to_string(),
+    )
+    .limits(limits)
+    .run()
+    .unwrap();
+    assert!(output.success());
+    assert_eq!(output.stdout, "19 120 64\n");
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_run_as() {
+    let err = Command::new("true".to_string())
+        .run_as(Some("no-such-user-librarian".to_string()))
+        .run()
+        .unwrap_err();
+    assert!(matches!(err, Error::UnknownUser(u) if u == "no-such-user-librarian"));
+    let err = Command::new("true".to_string())
+        .run_as(Some("root:no-such-group-librarian".to_string()))
+        .run()
+        .unwrap_err();
+    assert!(matches!(err, Error::UnknownGroup(g) if g == "no-such-group-librarian"));
+
+    // dropping privileges is only possible when running as root
+    if !nix::unistd::getuid().is_root() {
+        return;
+    }
+    let output = Command::new("echo $(id -u) $(id -g) $HOME".to_string())
+        .run_as(Some("65534:65534".to_string()))
+        .env(HashMap::from([("HOME".to_string(), "/tmp".to_string())]))
+        .run()
+        .unwrap();
+    assert!(output.success());
+    assert_eq!(output.stdout, "65534 65534 /tmp\n");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_run_sandbox() {
+    let dir: PathBuf = ["tests", "tmp", "command", "sandbox"].iter().collect();
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("writable")).unwrap();
+    fs::create_dir_all(dir.join("readable")).unwrap();
+    fs::write(dir.join("readable/file"), "contents").unwrap();
+    let sandbox = Sandbox {
+        read_only: ["/bin", "/usr", "/lib", "/lib64", "/etc"]
+            .iter()
+            .map(PathBuf::from)
+            .chain([dir.join("readable")])
+            .collect(),
+        read_write: vec![PathBuf::from("/dev"), dir.join("writable")],
+    };
+
+    let output = Command::new(
+        "cat readable/file > writable/file && cat writable/file && touch readable/other"
+            .to_string(),
+    )
+    .cwd(Some(dir.clone()))
+    .sandbox(Some(sandbox))
+    .run()
+    .unwrap();
+    assert!(!output.success());
+    assert_eq!(output.stdout, "contents");
+    assert!(output.stderr.contains("Permission denied"));
+    assert!(!dir.join("readable/other").exists());
+}
@@ -0,0 +1,116 @@
+use crate::command;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[cfg(test)]
+mod tests;
+
+// held while writing to, or rotating, any log so that entries from commands running at the
+// same time don't mix
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("An IO error was thrown while trying to write to the command log {0}")]
+    Io(PathBuf, #[source] io::Error),
+}
+
+/// A file the output of commands is appended to. Once the file reaches its maximum size it's
+/// renamed with a numbered suffix, `.1` being the most recent, and a new file is started
+#[derive(Debug)]
+pub struct Log<'a> {
+    path: &'a Path,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl<'a> Log<'a> {
+    pub fn new(path: &'a Path, max_bytes: u64, max_files: usize) -> Log<'a> {
+        Log {
+            path,
+            max_bytes,
+            max_files,
+        }
+    }
+
+    /// Appends the command's exit code and output, every line prefixed with the current time
+    /// and the subject the command ran against
+    pub fn write(&self, subject: &str, cmd: &str, output: &command::Output) -> Result<(), Error> {
+        let prefix = format!(
+            "{} {}",
+            chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            subject
+        );
+        let status = match (output.timed_out, output.exit_code) {
+            (true, _) => "timed out".to_string(),
+            (false, Some(c)) => format!("exited with {}", c),
+            (false, None) => "was terminated by a signal".to_string(),
+        };
+
+        let mut entry = format!(
+            "{} command {} after {:.3}s: {}\n",
+            prefix,
+            status,
+            output.duration.as_secs_f64(),
+            cmd.trim()
+        );
+        for (cur_name, cur_output) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            for cur_line in cur_output.lines() {
+                entry.push_str(&format!("{} {}: {}\n", prefix, cur_name, cur_line));
+            }
+        }
+
+        let _lock = match WRITE_LOCK.lock() {
+            Ok(l) => l,
+            Err(p) => p.into_inner(),
+        };
+        self.append(entry.as_bytes())
+            .map_err(|e| Error::Io(self.path.to_path_buf(), e))
+    }
+
+    fn append(&self, entry: &[u8]) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if let Ok(m) = fs::metadata(self.path) {
+            if m.len() > 0 && m.len() + entry.len() as u64 > self.max_bytes {
+                self.rotate()?;
+            }
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path)?;
+        file.write_all(entry)
+    }
+
+    // shifts the rotated files up by one, dropping the oldest, then makes the current file the
+    // most recent rotated one
+    fn rotate(&self) -> io::Result<()> {
+        if self.max_files == 0 {
+            return fs::remove_file(self.path);
+        }
+
+        let oldest = self.rotated_path(self.max_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for i in (1..self.max_files).rev() {
+            let cur_path = self.rotated_path(i);
+            if cur_path.exists() {
+                fs::rename(&cur_path, self.rotated_path(i + 1))?;
+            }
+        }
+
+        fs::rename(self.path, self.rotated_path(1))
+    }
+
+    fn rotated_path(&self, i: usize) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!(".{}", i));
+        PathBuf::from(path)
+    }
+}
@@ -0,0 +1,50 @@
+use super::*;
+use std::time::Duration;
+
+fn output(stdout: &str) -> command::Output {
+    command::Output {
+        exit_code: Some(0),
+        stdout: stdout.to_string(),
+        stderr: "warning\n".to_string(),
+        duration: Duration::from_millis(1500),
+        timed_out: false,
+    }
+}
+
+#[test]
+fn test_write() {
+    let dir: PathBuf = ["tests", "tmp", "command_log"].iter().collect();
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("library.log");
+
+    let log = Log::new(&path, 1 << 20, 2);
+    log.write("'a.mp3'", "convert a.mp3\n", &output("line 1\nline 2\n"))
+        .unwrap();
+    let contents = fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].ends_with(" 'a.mp3' command exited with 0 after 1.500s: convert a.mp3"));
+    assert!(lines[1].ends_with(" 'a.mp3' stdout: line 1"));
+    assert!(lines[2].ends_with(" 'a.mp3' stdout: line 2"));
+    assert!(lines[3].ends_with(" 'a.mp3' stderr: warning"));
+}
+
+#[test]
+fn test_write_rotation() {
+    let dir: PathBuf = ["tests", "tmp", "command_log_rotation"].iter().collect();
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("library.log");
+
+    // every entry is bigger than half of the maximum size so every write after the first
+    // rotates the log
+    let log = Log::new(&path, 200, 2);
+    for cur_entry in ["first", "second", "third", "fourth"] {
+        log.write("'a.mp3'", "true", &output(cur_entry)).unwrap();
+    }
+
+    let read = |p: &Path| fs::read_to_string(p).unwrap();
+    assert!(read(&path).contains("stdout: fourth"));
+    assert!(read(&dir.join("library.log.1")).contains("stdout: third"));
+    assert!(read(&dir.join("library.log.2")).contains("stdout: second"));
+    assert!(!dir.join("library.log.3").exists());
+}
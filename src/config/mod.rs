@@ -1,7 +1,31 @@
+use crate::action;
+use crate::checksum;
+use crate::command;
+use crate::state;
+use crate::template;
 use serde_derive::Deserialize;
 use std::collections::HashMap;
 use std::fs;
 use std::io;
+use std::path::{Path, PathBuf};
+
+// files of the same library processed at the same time, if the library doesn't set it
+const DEFAULT_CONCURRENCY: usize = 1;
+// files passed to a single run of a batch library's command, if the library doesn't set it
+const DEFAULT_BATCH_MAX_FILES: usize = 500;
+// size a command log can grow to before it's rotated, if the library doesn't set it
+const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+// rotated command logs kept, if the library doesn't set it
+const DEFAULT_LOG_MAX_FILES: usize = 5;
+// seconds running commands get to finish when Librarian is shutting down, if not set
+const DEFAULT_SHUTDOWN_GRACE_PERIOD: u64 = 30;
+
+// template variables that are only available when a library processes its files one at a time
+const PER_FILE_VARS: [&str; 3] = ["file_path", "mime_type", "parent_dir"];
+
+// prefixes for variable values that should be read from outside the config file
+const VAR_SOURCE_ENV: &str = "env:";
+const VAR_SOURCE_FILE: &str = "file:";
 
 #[cfg(test)]
 mod tests;
@@ -12,6 +36,26 @@ pub enum Error {
     Io(#[from] io::Error),
     #[error("An Error was thrown while trying to parse the config as TOML")]
     Toml(#[from] toml::de::Error),
+    #[error("Could not read the environment variable {0} referenced in the config")]
+    EnvVar(String),
+    #[error("Could not read the file {0} referenced in the config")]
+    VarFile(String, #[source] io::Error),
+    #[error("The {0} library should have exactly one of a command, steps or an action")]
+    Run(String),
+    #[error("The steps of the {0} library can't be empty")]
+    EmptySteps(String),
+    #[error("The {1} action of the {0} library requires a destination")]
+    ActionDest(String, action::Operation),
+    #[error("The webhook action of the {0} library requires a URL")]
+    ActionUrl(String),
+    #[error("The {0} library can't run an action in batches")]
+    BatchAction(String),
+    #[error("The {0} library can't stream files to the standard input of batch commands")]
+    BatchStdin(String),
+    #[error("The templates of the {0} library can't use the {1} variable since it processes files in batches")]
+    BatchVar(String, String),
+    #[error("The templates of the {0} library couldn't be parsed")]
+    Template(String, #[source] template::Error),
 }
 
 #[derive(Debug, Deserialize)]
@@ -19,6 +63,20 @@ pub enum Error {
 pub struct Config {
     pub libraries: HashMap<String, Libraries>,
     pub fs_watch: Option<FsWatch>,
+    pub templates: Option<HashMap<String, String>>,
+    pub template_dirs: Option<Vec<String>>,
+    pub vars: Option<HashMap<String, String>>,
+    /// The maximum number of files, across all libraries, processed at the same time
+    pub max_parallel_commands: Option<usize>,
+    /// Path to the journal the changes made by built-in file actions are recorded in
+    pub journal: Option<String>,
+    /// Path to the NDJSON file, Unix domain socket or FIFO a record of every processed file is
+    /// written to
+    pub events: Option<String>,
+    /// Path to the SQLite database of the files every library processed
+    pub state: Option<String>,
+    /// The number of seconds running commands get to finish when Librarian is shutting down
+    pub shutdown_grace_period: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -30,8 +88,233 @@ pub struct FsWatch {
 #[derive(Debug, Deserialize)]
 #[allow(dead_code)]
 pub struct Libraries {
-    pub command: String,
+    /// The library's key in the libraries table
+    #[serde(skip)]
+    pub name: String,
+    pub command: Option<String>,
+    /// Commands that run one after the other, used instead of `command`
+    pub steps: Option<Vec<Step>>,
+    /// A built-in file operation, used instead of `command`
+    pub action: Option<Action>,
     pub filter: Filter,
+    pub vars: Option<HashMap<String, String>>,
+    pub on_error: Option<OnError>,
+    /// The maximum number of seconds the command can run for
+    pub timeout: Option<u64>,
+    /// The number of times a failed command is retried
+    pub retries: Option<u32>,
+    /// The number of seconds to wait before the first retry, doubled for every other retry
+    pub retry_backoff: Option<u64>,
+    /// The exit codes that a command can be retried for. All failures are retried if not set
+    pub retry_exit_codes: Option<Vec<i32>>,
+    /// The maximum number of the library's files processed at the same time
+    pub concurrency: Option<usize>,
+    /// Environment variables set for the library's commands
+    pub env: Option<HashMap<String, String>>,
+    /// Whether the library's commands should not inherit Librarian's environment variables
+    pub clear_env: Option<bool>,
+    /// A template of the directory the library's commands run in
+    pub cwd: Option<String>,
+    /// The program, and its arguments, the library's commands are passed to
+    pub shell: Option<Vec<String>>,
+    /// Runs the library's commands once for many files, instead of once per file
+    pub batch: Option<Batch>,
+    /// What the library's commands read from their standard input
+    pub stdin: Option<Stdin>,
+    /// A template of the command that runs after the library's commands succeed for a file
+    pub on_success: Option<String>,
+    /// A template of the command that runs after the library's commands fail for a file
+    pub on_failure: Option<String>,
+    /// Where the output of the library's commands is written to
+    pub log: Option<Log>,
+    /// The niceness the library's commands run with, on Linux
+    pub nice: Option<i32>,
+    /// The IO scheduling class the library's commands run with, on Linux
+    pub ionice: Option<command::IoniceClass>,
+    /// Resource limits for the library's commands, on Linux
+    pub rlimits: Option<Rlimits>,
+    /// The user, and optionally the group, as `user:group`, the library's commands run as
+    pub run_as: Option<String>,
+    /// The only paths the library's commands can access, on Linux
+    pub sandbox: Option<Sandbox>,
+    /// When to skip files the library already processed, according to the state database
+    pub skip_if_processed: Option<state::SkipIfProcessed>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Rlimits {
+    /// The maximum number of seconds of CPU time
+    pub cpu: Option<u64>,
+    /// The maximum size, in bytes, of a command's virtual memory
+    pub address_space: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Sandbox {
+    pub read_only: Option<Vec<String>>,
+    /// Paths, in addition to the library's directories, the commands can modify
+    pub read_write: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Log {
+    pub path: String,
+    /// The size, in bytes, the log can grow to before it's rotated
+    pub max_bytes: Option<u64>,
+    /// The number of rotated logs to keep
+    pub max_files: Option<usize>,
+}
+
+impl Log {
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes.unwrap_or(DEFAULT_LOG_MAX_BYTES)
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Batch {
+    /// The maximum number of files passed to a single run of the commands
+    pub max_files: Option<usize>,
+}
+
+impl Batch {
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(DEFAULT_BATCH_MAX_FILES).max(1)
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[allow(dead_code)]
+pub struct Step {
+    pub command: String,
+    /// A Tera expression that has to be true for the step to run
+    pub when: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct Action {
+    #[serde(rename = "type")]
+    pub operation: action::Operation,
+    /// A template of the path the file is moved, copied or linked to, or of the directory that
+    /// is created
+    pub dest: Option<String>,
+    /// What to do if the destination already exists. Defaults to failing
+    pub on_conflict: Option<action::OnConflict>,
+    /// A template of the URL the webhook action sends its request to
+    pub url: Option<String>,
+    /// A template of the webhook's request body. Defaults to the template variables as a JSON
+    /// object
+    pub body: Option<String>,
+    /// Templates of the webhook's request headers
+    pub headers: Option<HashMap<String, String>>,
+}
+
+impl Libraries {
+    /// Returns the library's steps. A library with a single command has a single step
+    pub fn steps(&self) -> Vec<Step> {
+        match (&self.steps, &self.command) {
+            (Some(s), _) => s.clone(),
+            (None, Some(c)) => vec![Step {
+                command: c.clone(),
+                when: None,
+            }],
+            (None, None) => vec![],
+        }
+    }
+
+    /// Returns the niceness, IO scheduling class and resource limits of the library's commands
+    pub fn limits(&self) -> command::Limits {
+        let rlimits = self.rlimits.as_ref();
+        command::Limits {
+            nice: self.nice,
+            ionice: self.ionice,
+            cpu_seconds: rlimits.and_then(|r| r.cpu),
+            address_space: rlimits.and_then(|r| r.address_space),
+            open_files: rlimits.and_then(|r| r.open_files),
+        }
+    }
+
+    /// Returns the paths the library's commands can access, if they should be sandboxed. The
+    /// library's directories can always be modified
+    pub fn sandbox(&self) -> Option<command::Sandbox> {
+        let sandbox = self.sandbox.as_ref()?;
+        let to_paths = |p: &Option<Vec<String>>| -> Vec<PathBuf> {
+            p.iter().flatten().map(PathBuf::from).collect()
+        };
+        let mut read_write = to_paths(&sandbox.read_write);
+        read_write.extend(self.filter.directories.iter().map(PathBuf::from));
+        Some(command::Sandbox {
+            read_only: to_paths(&sandbox.read_only),
+            read_write,
+        })
+    }
+
+    // returns the first variable, only available when files are processed one at a time, that
+    // the library's templates, or the snippets they include or import, use
+    fn per_file_var(
+        &self,
+        snippets: &HashMap<String, String>,
+    ) -> std::result::Result<Option<String>, template::Error> {
+        let mut set = template::Set::with_snippets(snippets)?;
+        let mut templates = Vec::new();
+        for cur_template in [&self.command, &self.cwd, &self.on_success, &self.on_failure]
+            .into_iter()
+            .flatten()
+        {
+            templates.push(set.add(cur_template)?);
+        }
+        for cur_step in self.steps.iter().flatten() {
+            templates.push(set.add(&cur_step.command)?);
+            if let Some(w) = &cur_step.when {
+                templates.push(set.add_condition(w)?);
+            }
+        }
+
+        let checksum_vars = checksum::ALGORITHMS.iter().map(|a| a.as_str());
+        Ok(PER_FILE_VARS
+            .into_iter()
+            .chain(checksum_vars)
+            .find(|v| templates.iter().any(|t| t.references(v)))
+            .map(|v| v.to_string()))
+    }
+
+    /// Returns the maximum number of the library's files that should be processed at the same
+    /// time. Defaults to one since the library's commands might not be safe to run against
+    /// several files at once
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.unwrap_or(DEFAULT_CONCURRENCY)
+    }
+}
+
+/// What a library's commands read from their standard input
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Stdin {
+    /// The contents of the file
+    File,
+    /// The variables available to the command's template, as a JSON object
+    Json,
+    None,
+}
+
+/// What to do when a library's command exits with a non-zero exit code
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// Record the failure and continue processing the library's other files
+    Continue,
+    /// Stop processing the library's other files
+    Abort,
 }
 
 #[derive(Debug, Deserialize)]
@@ -39,6 +322,14 @@ pub struct Libraries {
 pub struct Filter {
     pub directories: Vec<String>,
     pub mime_type_regexes: Option<Vec<String>>,
+    pub checksum: Option<ChecksumFilter>,
+}
+
+#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+pub struct ChecksumFilter {
+    pub algorithm: checksum::Algorithm,
+    pub values: Vec<String>,
 }
 
 impl Config {
@@ -46,6 +337,160 @@ impl Config {
     pub fn new(config_path: &String) -> std::result::Result<Config, Error> {
         let contents = fs::read_to_string(config_path)?;
 
-        Ok(toml::from_str(&contents)?)
+        let mut config: Config = toml::from_str(&contents)?;
+        // relative paths in the config are relative to the config file
+        let config_dir = Path::new(config_path).parent().unwrap_or(Path::new(""));
+        config.load_template_dirs(config_dir)?;
+        resolve_vars(&mut config.vars, config_dir)?;
+        let empty_snippets = HashMap::new();
+        for (cur_name, cur_lib) in config.libraries.iter_mut() {
+            cur_lib.name = cur_name.clone();
+            let runs = [
+                cur_lib.command.is_some(),
+                cur_lib.steps.is_some(),
+                cur_lib.action.is_some(),
+            ];
+            if runs.iter().filter(|r| **r).count() != 1 {
+                return Err(Error::Run(cur_name.clone()));
+            }
+            if cur_lib.steps.as_ref().is_some_and(|s| s.is_empty()) {
+                return Err(Error::EmptySteps(cur_name.clone()));
+            }
+            if let Some(a) = &cur_lib.action {
+                if a.operation.has_dest() && a.dest.is_none() {
+                    return Err(Error::ActionDest(cur_name.clone(), a.operation));
+                }
+                if a.operation == action::Operation::Webhook && a.url.is_none() {
+                    return Err(Error::ActionUrl(cur_name.clone()));
+                }
+                if cur_lib.batch.is_some() {
+                    return Err(Error::BatchAction(cur_name.clone()));
+                }
+            }
+            if cur_lib.batch.is_some() && cur_lib.stdin == Some(Stdin::File) {
+                return Err(Error::BatchStdin(cur_name.clone()));
+            }
+            if cur_lib.batch.is_some() {
+                let snippets = config.templates.as_ref().unwrap_or(&empty_snippets);
+                match cur_lib.per_file_var(snippets) {
+                    Ok(Some(v)) => return Err(Error::BatchVar(cur_name.clone(), v)),
+                    Ok(None) => {}
+                    Err(e) => return Err(Error::Template(cur_name.clone(), e)),
+                }
+            }
+            resolve_vars(&mut cur_lib.vars, config_dir)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Returns the maximum number of files, across all libraries, that should be processed at
+    /// the same time. Defaults to the number of CPUs available
+    pub fn max_parallel_commands(&self) -> usize {
+        match self.max_parallel_commands {
+            Some(m) => m,
+            None => std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+        }
     }
+
+    pub fn shutdown_grace_period(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(
+            self.shutdown_grace_period
+                .unwrap_or(DEFAULT_SHUTDOWN_GRACE_PERIOD),
+        )
+    }
+
+    /// Returns the user-defined variables available to the library's templates. Variables
+    /// defined in the library take precedence over global ones with the same name
+    pub fn vars<'a>(&'a self, library: &'a Libraries) -> HashMap<&'a str, &'a str> {
+        let mut vars = HashMap::new();
+        for cur_vars in [&self.vars, &library.vars].into_iter().flatten() {
+            for (cur_key, cur_val) in cur_vars.iter() {
+                vars.insert(cur_key.as_str(), cur_val.as_str());
+            }
+        }
+
+        vars
+    }
+
+    // adds the files in the configured template directories to the shared templates, keyed
+    // by their path relative to the template directory. Snippets defined inline in the
+    // templates table take precedence over ones read from the directories. Relative
+    // directories are relative to the config's directory
+    fn load_template_dirs(&mut self, config_dir: &Path) -> std::result::Result<(), Error> {
+        let dirs = match &self.template_dirs {
+            Some(d) => d.clone(),
+            None => return Ok(()),
+        };
+
+        let templates = self.templates.get_or_insert_with(HashMap::new);
+        for cur_dir in dirs.iter() {
+            let mut dir_templates = HashMap::new();
+            let cur_dir = config_dir.join(cur_dir);
+            read_template_dir(&cur_dir, &cur_dir, &mut dir_templates)?;
+            for (name, content) in dir_templates {
+                templates.entry(name).or_insert(content);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_template_dir(
+    root: &Path,
+    dir: &Path,
+    templates: &mut HashMap<String, String>,
+) -> std::result::Result<(), Error> {
+    for cur_entry_res in fs::read_dir(dir)? {
+        let cur_entry = cur_entry_res?;
+        let cur_path = cur_entry.path();
+        if cur_entry.file_type()?.is_dir() {
+            read_template_dir(root, &cur_path, templates)?;
+            continue;
+        }
+
+        let name = match cur_path.strip_prefix(root) {
+            Ok(n) => n
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            Err(_) => continue,
+        };
+        templates.insert(name, fs::read_to_string(&cur_path)?);
+    }
+
+    Ok(())
+}
+
+// replaces variable values that reference an environment variable or a file with the contents
+// of what they reference. Relative file paths are relative to the config's directory
+fn resolve_vars(
+    vars: &mut Option<HashMap<String, String>>,
+    config_dir: &Path,
+) -> std::result::Result<(), Error> {
+    let vars = match vars {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+
+    for cur_val in vars.values_mut() {
+        if let Some(name) = cur_val.strip_prefix(VAR_SOURCE_ENV) {
+            *cur_val = match std::env::var(name) {
+                Ok(v) => v,
+                Err(_) => return Err(Error::EnvVar(name.to_string())),
+            };
+        } else if let Some(path) = cur_val.strip_prefix(VAR_SOURCE_FILE) {
+            let contents = match fs::read_to_string(config_dir.join(path)) {
+                Ok(c) => c,
+                Err(e) => return Err(Error::VarFile(path.to_string(), e)),
+            };
+            *cur_val = contents.trim_end_matches(['\r', '\n']).to_string();
+        }
+    }
+
+    Ok(())
 }
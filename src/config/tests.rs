@@ -4,18 +4,50 @@ use std::env;
 #[test]
 fn test_configs() {
     let ok_test_cases = if env::consts::OS == "windows" {
-        ["tests/configs/good-windows.toml".to_string()]
+        [
+            "tests/configs/good-windows.toml".to_string(),
+            "tests/configs/good-batch.toml".to_string(),
+        ]
     } else {
-        ["tests/configs/good.toml".to_string()]
+        [
+            "tests/configs/good.toml".to_string(),
+            "tests/configs/good-batch.toml".to_string(),
+        ]
     };
     for cur_case in ok_test_cases.iter() {
         Config::new(cur_case).unwrap(); // should panic if error is returned
     }
 
-    let err_test_cases = [(
-        "tests/configs/bad-missing-directories.toml".to_string(),
-        "An Error was thrown while trying to parse the config as TOML",
-    )];
+    let err_test_cases = [
+        (
+            "tests/configs/bad-missing-directories.toml".to_string(),
+            "An Error was thrown while trying to parse the config as TOML",
+        ),
+        (
+            "tests/configs/bad-command-and-steps.toml".to_string(),
+            "The music library should have exactly one of a command, steps or an action",
+        ),
+        (
+            "tests/configs/bad-empty-steps.toml".to_string(),
+            "The steps of the music library can't be empty",
+        ),
+        (
+            "tests/configs/bad-action-dest.toml".to_string(),
+            "The move action of the music library requires a destination",
+        ),
+        (
+            "tests/configs/bad-webhook-url.toml".to_string(),
+            "The webhook action of the documents library requires a URL",
+        ),
+        (
+            "tests/configs/bad-batch-var.toml".to_string(),
+            "The templates of the photos library can't use the parent_dir variable",
+        ),
+        (
+            "tests/configs/bad-batch-include.toml".to_string(),
+            "The templates of the photos library can't use the mime_type variable",
+        ),
+    ];
     for cur_case in err_test_cases.iter() {
         assert!(Config::new(&cur_case.0)
             .unwrap_err()
@@ -23,3 +55,27 @@ fn test_configs() {
             .contains(cur_case.1));
     }
 }
+
+#[test]
+fn test_vars() {
+    // only this test reads the variable, so setting it doesn't affect the other tests
+    env::set_var("LIBRARIAN_TEST_VARS_TOKEN", "t0k3n");
+    let conf = Config::new(&"tests/configs/good-vars.toml".to_string()).unwrap();
+
+    let music_vars = conf.vars(&conf.libraries["music"]);
+    assert_eq!(music_vars["music_root"], "/srv/music");
+    assert_eq!(music_vars["token"], "t0k3n");
+    assert_eq!(music_vars["password"], "s3cr3t");
+    assert!(!music_vars.contains_key("books_root"));
+
+    let books_vars = conf.vars(&conf.libraries["books"]);
+    assert_eq!(books_vars["music_root"], "/home/librarian/Music");
+    assert_eq!(books_vars["books_root"], "/srv/books");
+
+    assert!(
+        Config::new(&"tests/configs/bad-missing-env-var.toml".to_string())
+            .unwrap_err()
+            .to_string()
+            .contains("Could not read the environment variable LIBRARIAN_TEST_VARS_UNSET")
+    );
+}
@@ -0,0 +1,212 @@
+use serde_derive::Serialize;
+use std::fs;
+use std::io::{self, Write};
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+// the longest a write to a socket can block before the subscriber is dropped
+#[cfg(target_family = "unix")]
+const SOCKET_WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("An IO error was thrown while writing to the event stream '{0}'")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("An error was thrown while trying to write an event as JSON")]
+    Json(#[from] serde_json::Error),
+}
+
+/// How processing a file ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Processed,
+    Failed,
+    /// The library's action left the file as it was
+    Skipped,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Processed => "processed",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+        }
+    }
+}
+
+/// A file a library processed
+#[derive(Debug, Serialize)]
+pub struct Record<'a> {
+    pub library: &'a str,
+    pub path: &'a Path,
+    /// Is `None` for files processed in batches
+    pub mime_type: Option<&'a str>,
+    pub outcome: Outcome,
+    /// The number of seconds the library's commands, or action, ran for
+    pub duration: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Line<'a> {
+    /// RFC 3339 time the record was written
+    time: String,
+    #[serde(flatten)]
+    record: &'a Record<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    File,
+    #[cfg(target_family = "unix")]
+    Socket,
+    #[cfg(target_family = "unix")]
+    Fifo,
+}
+
+#[derive(Debug)]
+enum Writer {
+    File(fs::File),
+    #[cfg(target_family = "unix")]
+    Socket(std::os::unix::net::UnixStream),
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Writer::File(f) => f.write(buf),
+            #[cfg(target_family = "unix")]
+            Writer::Socket(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Writer::File(f) => f.flush(),
+            #[cfg(target_family = "unix")]
+            Writer::Socket(s) => s.flush(),
+        }
+    }
+}
+
+/// Where a JSON object, on its own line, is written for every file Librarian processes. Can be
+/// an append-only file, or, on Unix, a listening Unix domain socket or a FIFO. Records are
+/// dropped while nothing is listening on a socket or reading from a FIFO, and Librarian
+/// reconnects once something is
+#[derive(Debug)]
+pub struct Sink {
+    path: PathBuf,
+    kind: Kind,
+    writer: Mutex<Option<Writer>>,
+}
+
+impl Sink {
+    pub fn open(path: &Path) -> Result<Sink, Error> {
+        let io_err = |e| Error::Io(path.to_path_buf(), e);
+        let kind = match fs::metadata(path) {
+            #[cfg(target_family = "unix")]
+            Ok(m) if m.file_type().is_socket() => Kind::Socket,
+            #[cfg(target_family = "unix")]
+            Ok(m) if m.file_type().is_fifo() => Kind::Fifo,
+            Ok(_) => Kind::File,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent).map_err(io_err)?;
+                }
+                Kind::File
+            }
+            Err(e) => return Err(io_err(e)),
+        };
+
+        let sink = Sink {
+            path: path.to_path_buf(),
+            kind,
+            writer: Mutex::new(None),
+        };
+        if kind == Kind::File {
+            *sink.lock() = Some(sink.connect().map_err(io_err)?);
+        }
+        Ok(sink)
+    }
+
+    pub fn write(&self, record: &Record) -> Result<(), Error> {
+        let line = Line {
+            time: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            record,
+        };
+        let mut line = serde_json::to_vec(&line)?;
+        line.push(b'\n');
+
+        // the whole line is written at once so that records from different threads don't mix
+        let mut writer = self.lock();
+        if writer.is_none() {
+            match self.connect() {
+                Ok(w) => *writer = Some(w),
+                // nothing is listening yet
+                Err(_) => return Ok(()),
+            }
+        }
+        let result = match writer.as_mut() {
+            Some(w) => w.write_all(&line),
+            None => Ok(()),
+        };
+        match (result, self.kind) {
+            (Ok(()), _) => Ok(()),
+            (Err(e), Kind::File) => Err(Error::Io(self.path.clone(), e)),
+            // the subscriber went away, so reconnect when the next record is written
+            #[cfg(target_family = "unix")]
+            (Err(_), _) => {
+                *writer = None;
+                Ok(())
+            }
+        }
+    }
+
+    fn connect(&self) -> io::Result<Writer> {
+        match self.kind {
+            Kind::File => Ok(Writer::File(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            )),
+            #[cfg(target_family = "unix")]
+            Kind::Socket => {
+                let stream = std::os::unix::net::UnixStream::connect(&self.path)?;
+                stream.set_write_timeout(Some(SOCKET_WRITE_TIMEOUT))?;
+                Ok(Writer::Socket(stream))
+            }
+            #[cfg(target_family = "unix")]
+            Kind::Fifo => {
+                use nix::fcntl::{fcntl, FcntlArg, OFlag};
+                use std::os::unix::fs::OpenOptionsExt;
+                use std::os::unix::io::AsRawFd;
+
+                // opening a FIFO without a reader blocks, unless it's opened in non-blocking
+                // mode, which fails instead. Writes block like they do for other files
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .custom_flags(OFlag::O_NONBLOCK.bits())
+                    .open(&self.path)?;
+                fcntl(file.as_raw_fd(), FcntlArg::F_SETFL(OFlag::empty()))?;
+                Ok(Writer::File(file))
+            }
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Writer>> {
+        match self.writer.lock() {
+            Ok(w) => w,
+            Err(p) => p.into_inner(),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use super::*;
+#[cfg(target_family = "unix")]
+use std::io::{BufRead, BufReader, Read};
+
+fn tmp_path(name: &str) -> PathBuf {
+    ["tests", "tmp", "events", name].iter().collect()
+}
+
+fn record(path: &Path) -> Record<'_> {
+    Record {
+        library: "music",
+        path,
+        mime_type: Some("audio/flac"),
+        outcome: Outcome::Processed,
+        duration: 1.5,
+        error: None,
+    }
+}
+
+fn parse(line: &str) -> serde_json::Value {
+    let value: serde_json::Value = serde_json::from_str(line).unwrap();
+    assert!(value["time"].is_string());
+    value
+}
+
+#[test]
+fn test_write_file() {
+    let path = tmp_path("events.ndjson");
+    let _ = fs::remove_file(&path);
+    let sink = Sink::open(&path).unwrap();
+    sink.write(&record(Path::new("a.flac"))).unwrap();
+    sink.write(&Record {
+        outcome: Outcome::Failed,
+        error: Some("The command failed".to_string()),
+        ..record(Path::new("b.flac"))
+    })
+    .unwrap();
+
+    let contents = fs::read_to_string(&path).unwrap();
+    let lines: Vec<serde_json::Value> = contents.lines().map(parse).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["library"], "music");
+    assert_eq!(lines[0]["path"], "a.flac");
+    assert_eq!(lines[0]["mime_type"], "audio/flac");
+    assert_eq!(lines[0]["outcome"], "processed");
+    assert_eq!(lines[0]["duration"], 1.5);
+    assert!(lines[0].get("error").is_none());
+    assert_eq!(lines[1]["outcome"], "failed");
+    assert_eq!(lines[1]["error"], "The command failed");
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_write_socket() {
+    let path = tmp_path("events.sock");
+    let _ = fs::remove_file(&path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+    let sink = Sink::open(&path).unwrap();
+
+    sink.write(&record(Path::new("a.flac"))).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).unwrap();
+    assert_eq!(parse(&line)["path"], "a.flac");
+
+    // records are dropped once the subscriber goes away, until it reconnects
+    drop(stream);
+    sink.write(&record(Path::new("b.flac"))).unwrap();
+    sink.write(&record(Path::new("c.flac"))).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).unwrap();
+    assert!(["b.flac", "c.flac"].contains(&parse(&line)["path"].as_str().unwrap()));
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_write_fifo() {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let path = tmp_path("events.fifo");
+    let _ = fs::remove_file(&path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    nix::unistd::mkfifo(&path, nix::sys::stat::Mode::S_IRWXU).unwrap();
+    let sink = Sink::open(&path).unwrap();
+
+    // nothing is reading from the FIFO, so the record is dropped
+    sink.write(&record(Path::new("a.flac"))).unwrap();
+
+    let mut reader = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::fcntl::OFlag::O_NONBLOCK.bits())
+        .open(&path)
+        .unwrap();
+    sink.write(&record(Path::new("b.flac"))).unwrap();
+    let mut buf = [0; 4096];
+    let num_read = reader.read(&mut buf).unwrap();
+    let contents = String::from_utf8_lossy(&buf[..num_read]);
+    let lines: Vec<serde_json::Value> = contents.lines().map(parse).collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["path"], "b.flac");
+}
@@ -1,10 +1,13 @@
 use crate::config::FsWatch;
 use std::collections::HashSet;
 use std::path::PathBuf;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::time::Duration;
 use ttl_cache::TtlCache;
 
+// how often to check whether watching should stop while there are no notifications
+const UNWATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 mod fanotify_notifier;
 mod notify_notifier;
 
@@ -142,7 +145,7 @@ impl<'a> Notify<'a> {
         }
 
         loop {
-            match notification_receiver.recv() {
+            match notification_receiver.recv_timeout(UNWATCH_POLL_INTERVAL) {
                 Ok(Notification { path, op }) => {
                     if !path.is_dir() && !op.contains(FsOp::REMOVE) {
                         if let Some(path_str) = path.as_os_str().to_str() {
@@ -158,6 +161,7 @@ impl<'a> Notify<'a> {
                         }
                     }
                 }
+                Err(RecvTimeoutError::Timeout) => {}
                 Err(e) => {
                     panic!("FS watcher returned an error: {}", e);
                 }
@@ -166,6 +170,7 @@ impl<'a> Notify<'a> {
             if let Ok(k) = self.unwatch_receiver.try_recv() {
                 if k {
                     i.stop_watching();
+                    fa.stop_watching();
                     break;
                 }
             }
@@ -174,7 +179,6 @@ impl<'a> Notify<'a> {
         Ok(())
     }
 
-    #[allow(dead_code)]
     pub fn unwatch(unwatch_sender: &Sender<bool>) -> Result<(), Error> {
         unwatch_sender.send(true)?;
         Ok(())
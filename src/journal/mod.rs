@@ -0,0 +1,243 @@
+use crate::action;
+use serde_derive::{Deserialize, Serialize};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("An IO error was thrown while accessing the journal")]
+    Io(#[from] io::Error),
+    #[error("An error was thrown while trying to parse or write a journal entry as JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("The journal doesn't have any changes for the run {0}")]
+    UnknownRun(String),
+    #[error("The run {0} has already been undone")]
+    AlreadyUndone(String),
+    #[error("'{0}' already exists")]
+    Exists(PathBuf),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    run_id: String,
+    /// Seconds since the Unix epoch
+    time: u64,
+    #[serde(flatten)]
+    event: Event,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Change {
+        library: String,
+        change: action::Change,
+    },
+    /// The change was reversed while undoing the run
+    Reverted { change: action::Change },
+    /// All the run's changes were reversed
+    Undone,
+}
+
+/// The number of changes from a run that were reversed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub undone: u64,
+    pub failed: u64,
+}
+
+/// An append-only file, with one JSON object per line, of the changes built-in file actions
+/// made during a run of Librarian
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    run_id: String,
+    file: Mutex<fs::File>,
+}
+
+impl Journal {
+    pub fn open(path: &Path, run_id: String) -> Result<Journal, Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        Ok(Journal {
+            path: path.to_path_buf(),
+            run_id,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Returns the directory files deleted, or replaced, during the run are moved to, so that
+    /// they can be restored
+    pub fn trash_dir(&self) -> PathBuf {
+        trash_dir(&self.path, &self.run_id)
+    }
+
+    pub fn record(&self, library: &str, change: action::Change) -> Result<(), Error> {
+        let change = match change {
+            action::Change::Moved { from, to } => action::Change::Moved {
+                from: absolute(&from)?,
+                to: absolute(&to)?,
+            },
+            action::Change::Created { path } => action::Change::Created {
+                path: absolute(&path)?,
+            },
+            action::Change::CreatedDir { path } => action::Change::CreatedDir {
+                path: absolute(&path)?,
+            },
+            action::Change::Trashed { path, trash } => action::Change::Trashed {
+                path: absolute(&path)?,
+                trash: absolute(&trash)?,
+            },
+        };
+
+        self.append(Event::Change {
+            library: library.to_string(),
+            change,
+        })
+    }
+
+    fn append(&self, event: Event) -> Result<(), Error> {
+        let entry = Entry {
+            run_id: self.run_id.clone(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            event,
+        };
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        // the whole line is written at once so that entries from different threads don't mix
+        let mut file = match self.file.lock() {
+            Ok(f) => f,
+            Err(p) => p.into_inner(),
+        };
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+/// Returns a new ID for a run of Librarian, made up of the time it started and its process ID
+pub fn new_run_id() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    format!("{}-{}", secs, std::process::id())
+}
+
+/// Reverses the changes recorded in the journal for the run, most recent first. Changes that
+/// can't be reversed are reported and skipped, and are the only ones reversed if the run is
+/// undone again
+pub fn undo(path: &Path, run_id: &str) -> Result<Summary, Error> {
+    let mut changes = Vec::new();
+    let mut reverted = Vec::new();
+    for cur_line in io::BufReader::new(fs::File::open(path)?).lines() {
+        let cur_line = cur_line?;
+        if cur_line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: Entry = serde_json::from_str(&cur_line)?;
+        if entry.run_id != run_id {
+            continue;
+        }
+        match entry.event {
+            Event::Change { change, .. } => changes.push(change),
+            Event::Reverted { change } => reverted.push(change),
+            Event::Undone => return Err(Error::AlreadyUndone(run_id.to_string())),
+        }
+    }
+    if changes.is_empty() {
+        return Err(Error::UnknownRun(run_id.to_string()));
+    }
+    // skip the changes reversed by an earlier attempt to undo the run
+    for cur_change in reverted {
+        if let Some(i) = changes.iter().rposition(|c| *c == cur_change) {
+            changes.remove(i);
+        }
+    }
+
+    let journal = Journal::open(path, run_id.to_string())?;
+    let mut summary = Summary::default();
+    for cur_change in changes.into_iter().rev() {
+        match reverse(&cur_change) {
+            Ok(()) => {
+                summary.undone += 1;
+                journal.append(Event::Reverted { change: cur_change })?;
+            }
+            Err(e) => {
+                eprintln!("Could not undo {}: {}", cur_change, error_str(&e));
+                summary.failed += 1;
+            }
+        }
+    }
+    // the trash directory is only removed if all the files in it were restored
+    let _ = fs::remove_dir(trash_dir(path, run_id));
+
+    if summary.failed == 0 {
+        journal.append(Event::Undone)?;
+    }
+    Ok(summary)
+}
+
+fn reverse(change: &action::Change) -> Result<(), Error> {
+    match change {
+        action::Change::Moved { from, to } => restore(to, from),
+        action::Change::Created { path } => Ok(fs::remove_file(path)?),
+        action::Change::CreatedDir { path } => Ok(fs::remove_dir(path)?),
+        action::Change::Trashed { path, trash } => restore(trash, path),
+    }
+}
+
+// moves the file back to where it was, without replacing anything that is there now
+fn restore(from: &Path, to: &Path) -> Result<(), Error> {
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(Error::Exists(to.to_path_buf()));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    Ok(action::move_file(from, to)?)
+}
+
+fn trash_dir(journal_path: &Path, run_id: &str) -> PathBuf {
+    let mut dir = OsString::from(journal_path.as_os_str());
+    dir.push(".trash");
+    PathBuf::from(dir).join(run_id)
+}
+
+/// Returns the path relative to the current directory if it isn't already absolute
+pub fn absolute(path: &Path) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        return Ok(path.to_path_buf());
+    }
+
+    Ok(std::env::current_dir()?.join(path))
+}
+
+fn error_str(error: &Error) -> String {
+    match std::error::Error::source(error) {
+        Some(s) => format!("{}: {}", error, s),
+        None => error.to_string(),
+    }
+}
@@ -0,0 +1,149 @@
+use super::*;
+use action::{Action, OnConflict, Operation};
+
+#[test]
+fn test_undo() {
+    let dir: PathBuf = ["tests", "tmp", "journal"].iter().collect();
+    let _ = fs::remove_dir_all(&dir);
+    let journal_path = dir.join("journal.ndjson");
+    let source = dir.join("files/track.mp3");
+    let dest = dir.join("music/album/track.mp3");
+    let deleted = dir.join("files/cover.jpg");
+    fs::create_dir_all(dest.parent().unwrap()).unwrap();
+    fs::create_dir_all(source.parent().unwrap()).unwrap();
+    fs::write(&source, "new").unwrap();
+    fs::write(&dest, "old").unwrap();
+    fs::write(&deleted, "cover").unwrap();
+
+    let journal = Journal::open(&journal_path, "run-1".to_string()).unwrap();
+    let mut changes = Vec::new();
+    Action::new(Operation::Move, &source, Some(dest.clone()))
+        .on_conflict(OnConflict::Overwrite)
+        .trash(Some(journal.trash_dir()))
+        .journal(Some(&journal), "music")
+        .run(&mut changes)
+        .unwrap();
+    Action::new(Operation::Copy, &dest, Some(dir.join("backup/track.mp3")))
+        .trash(Some(journal.trash_dir()))
+        .journal(Some(&journal), "music")
+        .run(&mut changes)
+        .unwrap();
+    Action::new(Operation::Delete, &deleted, None)
+        .trash(Some(journal.trash_dir()))
+        .journal(Some(&journal), "music")
+        .run(&mut changes)
+        .unwrap();
+    assert!(!source.exists());
+    assert!(!deleted.exists());
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+
+    assert!(matches!(
+        undo(&journal_path, "run-2").unwrap_err(),
+        Error::UnknownRun(_)
+    ));
+
+    let summary = undo(&journal_path, "run-1").unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            undone: 5,
+            failed: 0
+        }
+    );
+    assert_eq!(fs::read_to_string(&source).unwrap(), "new");
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+    assert_eq!(fs::read_to_string(&deleted).unwrap(), "cover");
+    assert!(!dir.join("backup").exists());
+    assert!(!journal.trash_dir().exists());
+
+    assert!(matches!(
+        undo(&journal_path, "run-1").unwrap_err(),
+        Error::AlreadyUndone(_)
+    ));
+}
+
+#[test]
+fn test_undo_retry() {
+    let dir: PathBuf = ["tests", "tmp", "journal_retry"].iter().collect();
+    let _ = fs::remove_dir_all(&dir);
+    let journal_path = dir.join("journal.ndjson");
+    let source = dir.join("files/track.mp3");
+    let dest = dir.join("music/track.mp3");
+    fs::create_dir_all(source.parent().unwrap()).unwrap();
+    fs::write(&source, "track").unwrap();
+
+    let journal = Journal::open(&journal_path, "run-1".to_string()).unwrap();
+    let mut changes = Vec::new();
+    Action::new(Operation::Move, &source, Some(dest.clone()))
+        .run(&mut changes)
+        .unwrap();
+    Action::new(Operation::Copy, &dest, Some(dir.join("backup/track.mp3")))
+        .run(&mut changes)
+        .unwrap();
+    for cur_change in changes {
+        journal.record("music", cur_change).unwrap();
+    }
+
+    // moving the track back, and so removing its directory, fails since a file is now where it
+    // was
+    fs::write(&source, "other").unwrap();
+    let summary = undo(&journal_path, "run-1").unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            undone: 2,
+            failed: 2
+        }
+    );
+    assert!(!dir.join("backup/track.mp3").exists());
+
+    // undoing the run again only retries the changes that failed
+    fs::remove_file(&source).unwrap();
+    let summary = undo(&journal_path, "run-1").unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            undone: 2,
+            failed: 0
+        }
+    );
+    assert_eq!(fs::read_to_string(&source).unwrap(), "track");
+
+    assert!(matches!(
+        undo(&journal_path, "run-1").unwrap_err(),
+        Error::AlreadyUndone(_)
+    ));
+}
+
+#[test]
+fn test_undo_failed_action() {
+    let dir: PathBuf = ["tests", "tmp", "journal_failed_action"].iter().collect();
+    let _ = fs::remove_dir_all(&dir);
+    let journal_path = dir.join("journal.ndjson");
+    let dest = dir.join("music/track.mp3");
+    fs::create_dir_all(dest.parent().unwrap()).unwrap();
+    fs::write(&dest, "old").unwrap();
+
+    // the destination is trashed before the move fails, which is journaled all the same
+    let journal = Journal::open(&journal_path, "run-1".to_string()).unwrap();
+    let mut changes = Vec::new();
+    let missing = dir.join("files/track.mp3");
+    assert!(Action::new(Operation::Move, &missing, Some(dest.clone()))
+        .on_conflict(OnConflict::Overwrite)
+        .trash(Some(journal.trash_dir()))
+        .journal(Some(&journal), "music")
+        .run(&mut changes)
+        .is_err());
+    assert_eq!(changes.len(), 1);
+    assert!(!dest.exists());
+
+    let summary = undo(&journal_path, "run-1").unwrap();
+    assert_eq!(
+        summary,
+        Summary {
+            undone: 1,
+            failed: 0
+        }
+    );
+    assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+}
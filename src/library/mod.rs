@@ -1,22 +1,48 @@
+use crate::action;
+use crate::checksum;
+use crate::command;
+use crate::command_log;
 use crate::config;
+use crate::events;
+use crate::journal;
 use crate::mime_type;
+use crate::state;
 use crate::template;
-use std::collections;
-use std::env;
+use crate::webhook;
+use crate::worker_pool;
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error as _;
+use std::fmt;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[cfg(test)]
 mod tests;
 
 const TEMPLATE_VAR_FILE_PATH: &str = "file_path";
 const TEMPLATE_VAR_MIME_TYPE: &str = "mime_type";
+const TEMPLATE_VAR_PARENT_DIR: &str = "parent_dir";
+const TEMPLATE_VAR_STEPS: &str = "steps";
+const TEMPLATE_VAR_FILES: &str = "files";
+const TEMPLATE_VAR_EXIT_CODE: &str = "exit_code";
+const TEMPLATE_VAR_STDERR: &str = "stderr";
+const TEMPLATE_VAR_DURATION: &str = "duration";
+// seconds to wait before the first retry of a failed command, if the library doesn't set one
+const DEFAULT_RETRY_BACKOFF: u64 = 1;
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("A config error was thrown")]
     Config(#[from] config::Error),
+    #[error("A checksum error was thrown")]
+    Checksum(#[from] checksum::Error),
     #[error("A MIME type error was thrown")]
     Mime(#[from] mime_type::Error),
     #[error("A templating error was thrown")]
@@ -27,69 +53,536 @@ pub enum Error {
     Regex(#[from] regex::Error),
     #[error("Could not read path {0}")]
     ReadPath(Box<Path>),
+    #[error("A JSON serialization error was thrown")]
+    Json(#[from] serde_json::Error),
+    #[error("A command error was thrown")]
+    Command(#[from] command::Error),
+    #[error("A journal error was thrown")]
+    Journal(#[from] journal::Error),
+    #[error("A state database error was thrown")]
+    State(#[from] state::Error),
+    #[error("The command for {0} {1}")]
+    CommandFailed(String, String),
+}
+
+/// The number of files a library's command ran against
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    /// Files the command ran successfully against
+    pub processed: u64,
+    /// Files the command exited with a non-zero exit code for
+    pub failed: u64,
+}
+
+impl Summary {
+    fn processed() -> Summary {
+        Summary {
+            processed: 1,
+            failed: 0,
+        }
+    }
+
+    fn failed() -> Summary {
+        Summary {
+            processed: 0,
+            failed: 1,
+        }
+    }
+
+    // the summary of running the library's commands against the target, assuming they ran
+    // the same way for every file in the target
+    fn for_target(summary: Summary, target: &Target) -> Summary {
+        Summary {
+            processed: summary.processed * target.len(),
+            failed: summary.failed * target.len(),
+        }
+    }
+}
+
+impl AddAssign for Summary {
+    fn add_assign(&mut self, other: Summary) {
+        self.processed += other.processed;
+        self.failed += other.failed;
+    }
+}
+
+/// Where libraries record what they do during a run of Librarian
+#[derive(Debug, Default, Clone)]
+pub struct Records {
+    pub journal: Option<Arc<journal::Journal>>,
+    pub events: Option<Arc<events::Sink>>,
+    pub state: Option<Arc<state::State>>,
+}
+
+/// How the rendered commands should be printed when not running them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DryRun {
+    Text,
+    /// One JSON object, with the library, command and template variables, per file
+    Json,
+}
+
+type Checksums = HashMap<checksum::Algorithm, String>;
+
+/// A library's commands, rendered against a file that passed the library's filters
+#[derive(Debug)]
+pub struct Rendered {
+    /// The commands for each of the library's steps that would run. Placeholders are used for
+    /// the outputs of earlier steps
+    pub commands: Vec<String>,
+    /// The variables that were available to the command's template
+    pub context: BTreeMap<String, String>,
+}
+
+/// What a library's commands run against, a single file or a batch of files
+#[derive(Debug, Clone, Copy)]
+enum Target<'b> {
+    File(&'b Path),
+    Batch(&'b [PathBuf]),
+}
+
+impl Target<'_> {
+    fn len(&self) -> u64 {
+        match self {
+            Target::File(_) => 1,
+            Target::Batch(f) => f.len() as u64,
+        }
+    }
+}
+
+impl fmt::Display for Target<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::File(p) => write!(f, "'{}'", p.display()),
+            Target::Batch(b) => write!(f, "a batch of {} files", b.len()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Templates {
+    steps: Vec<StepTemplates>,
+    cwd: Option<template::Template>,
+    action_dest: Option<template::Template>,
+    webhook: Option<WebhookTemplates>,
+    on_success: Option<template::Template>,
+    on_failure: Option<template::Template>,
+    // the Tera instance the templates above are rendered with
+    set: template::Set,
+}
+
+impl Templates {
+    fn references(&self, var: &str) -> bool {
+        self.steps.iter().any(|s| s.references(var))
+            || self.webhook.as_ref().is_some_and(|w| w.references(var))
+            || [
+                &self.cwd,
+                &self.action_dest,
+                &self.on_success,
+                &self.on_failure,
+            ]
+            .into_iter()
+            .flatten()
+            .any(|t| t.references(var))
+    }
+}
+
+/// How a library's commands, or action, ended for a file. Available to the library's hooks
+struct Outcome<'b> {
+    exit_code: Option<i32>,
+    stderr: &'b str,
+    duration: Duration,
+}
+
+#[derive(Debug)]
+struct StepTemplates {
+    command: template::Template,
+    when: Option<template::Template>,
+}
+
+impl StepTemplates {
+    fn references(&self, var: &str) -> bool {
+        self.command.references(var) || self.when.as_ref().is_some_and(|w| w.references(var))
+    }
+
+    fn should_run(&self, set: &template::Set, context: &tera::Context) -> Result<bool, Error> {
+        match &self.when {
+            Some(w) => Ok(set.render_condition(w, context)?),
+            None => Ok(true),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct WebhookTemplates {
+    url: template::Template,
+    body: Option<template::Template>,
+    headers: Vec<(String, template::Template)>,
+}
+
+impl WebhookTemplates {
+    fn references(&self, var: &str) -> bool {
+        self.url.references(var)
+            || self.body.as_ref().is_some_and(|b| b.references(var))
+            || self.headers.iter().any(|(_, h)| h.references(var))
+    }
+
+    // renders the request, sending the file's variables as a JSON object if the library
+    // doesn't set a body. User-defined variables are left out since they can hold secrets
+    fn render(
+        &self,
+        set: &template::Set,
+        variables: &BTreeMap<String, String>,
+        context: &tera::Context,
+    ) -> Result<webhook::Webhook, Error> {
+        let body = match &self.body {
+            Some(b) => set.render(b, context)?,
+            None => {
+                let checksum_vars = checksum::ALGORITHMS.iter().map(|a| a.as_str());
+                let file_vars: BTreeMap<&str, &str> = [
+                    TEMPLATE_VAR_FILE_PATH,
+                    TEMPLATE_VAR_MIME_TYPE,
+                    TEMPLATE_VAR_PARENT_DIR,
+                ]
+                .into_iter()
+                .chain(checksum_vars)
+                .filter_map(|k| variables.get(k).map(|v| (k, v.as_str())))
+                .collect();
+                serde_json::to_string(&file_vars)?
+            }
+        };
+        let mut headers = Vec::new();
+        for (cur_key, cur_val) in self.headers.iter() {
+            headers.push((cur_key.clone(), set.render(cur_val, context)?));
+        }
+
+        Ok(webhook::Webhook::new(set.render(&self.url, context)?, body).headers(headers))
+    }
+}
+
+/// The output of a step, available to the templates of later steps as `steps.<index>`
+#[derive(Debug, Serialize)]
+struct StepOutput {
+    stdout: String,
+    stderr: String,
+    exit_code: Option<i32>,
+    skipped: bool,
+}
+
+impl StepOutput {
+    fn placeholder(step: usize) -> StepOutput {
+        StepOutput {
+            stdout: format!("<stdout of step {}>", step),
+            stderr: format!("<stderr of step {}>", step),
+            exit_code: Some(0),
+            skipped: false,
+        }
+    }
+
+    fn skipped() -> StepOutput {
+        StepOutput {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: None,
+            skipped: true,
+        }
+    }
+}
+
+impl From<&command::Output> for StepOutput {
+    fn from(output: &command::Output) -> StepOutput {
+        StepOutput {
+            stdout: output.stdout.trim_end_matches(['\r', '\n']).to_string(),
+            stderr: output.stderr.clone(),
+            exit_code: output.exit_code,
+            skipped: false,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Library<'a> {
+    global_config: &'a config::Config,
     config: &'a config::Libraries,
-    skip_running_commands: &'a bool,
+    dry_run: &'a Option<DryRun>,
+    journal: Option<&'a journal::Journal>,
+    events: Option<&'a events::Sink>,
+    state: Option<&'a state::State>,
+    // the library's compiled templates, shared by every `Library` processing its files
+    template_cache: Arc<OnceLock<Templates>>,
 }
 
 impl<'a> Library<'a> {
     #[allow(dead_code)]
-    pub fn new(config: &'a config::Libraries, skip_running_commands: &'a bool) -> Library<'a> {
+    pub fn new(
+        global_config: &'a config::Config,
+        config: &'a config::Libraries,
+        dry_run: &'a Option<DryRun>,
+    ) -> Library<'a> {
         Library {
+            global_config,
             config,
-            skip_running_commands,
+            dry_run,
+            journal: None,
+            events: None,
+            state: None,
+            template_cache: Arc::new(OnceLock::new()),
         }
     }
 
+    /// Sets the journal the changes made by the library's action are recorded in
+    pub fn journal(mut self, journal: Option<&'a journal::Journal>) -> Library<'a> {
+        self.journal = journal;
+        self
+    }
+
+    /// Sets the event stream a record of every file the library processes is written to
+    pub fn events(mut self, events: Option<&'a events::Sink>) -> Library<'a> {
+        self.events = events;
+        self
+    }
+
+    /// Sets the database the files the library processes are tracked in
+    pub fn state(mut self, state: Option<&'a state::State>) -> Library<'a> {
+        self.state = state;
+        self
+    }
+
+    // shares the library's compiled templates with other instances of the library, so that the
+    // templates are only compiled once
+    fn template_cache(mut self, template_cache: &Arc<OnceLock<Templates>>) -> Library<'a> {
+        self.template_cache = Arc::clone(template_cache);
+        self
+    }
+
+    /// Sets the journal, event stream and state database the library records what it does in
+    pub fn records(self, records: &'a Records) -> Library<'a> {
+        self.journal(records.journal.as_deref())
+            .events(records.events.as_deref())
+            .state(records.state.as_deref())
+    }
+
     #[allow(dead_code)]
-    pub fn process(&self, path: Option<&Path>) -> Result<u64, Error> {
-        let mut num_processed_files: u64 = 0;
+    pub fn process(&self, path: Option<&Path>) -> Result<Summary, Error> {
+        let mut summary = Summary::default();
+        let files = self.files(path)?;
+        if self.config.batch.is_some() {
+            for cur_batch in self.batches(files)? {
+                summary += self.process_batch(&cur_batch)?;
+            }
+        } else {
+            for cur_file in files {
+                summary += self.process_file(&cur_file)?;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Returns the files in the path, or in the library's directories if no path is provided
+    pub fn files(&self, path: Option<&Path>) -> Result<Vec<PathBuf>, Error> {
+        let mut files = Vec::new();
 
         if let Some(p) = path {
             if p.is_dir() {
-                return self.process_dir(p);
+                list_dir(p, &mut files)?;
             } else {
-                let mut num_processed = 0;
-                if self.process_file(p)? {
-                    num_processed += 1;
-                }
-
-                return Ok(num_processed);
+                files.push(p.to_path_buf());
             }
+
+            return Ok(files);
         }
 
         for cur_dir in self.config.filter.directories.iter() {
-            num_processed_files += self.process_dir(Path::new(cur_dir))?;
+            list_dir(Path::new(cur_dir), &mut files)?;
+        }
+        Ok(files)
+    }
+
+    fn process_file(&self, path: &Path) -> Result<Summary, Error> {
+        let (mime_type, checksums) = match self.filter(path)? {
+            Some(f) => f,
+            None => return Ok(Summary::default()),
+        };
+        if self.is_processed(path)? {
+            return Ok(Summary::default());
+        }
+
+        // run the command if the file passes the filters
+        let start = Instant::now();
+        let result = self.run_command(path, mime_type.as_str(), checksums);
+        self.record_event(
+            &Target::File(path),
+            Some(mime_type.as_str()),
+            &result,
+            start.elapsed(),
+        );
+        self.record_state(path, &result)?;
+        result
+    }
+
+    // returns the files that pass the library's filters, split into batches of at most the
+    // library's maximum batch size
+    fn batches(&self, files: Vec<PathBuf>) -> Result<Vec<Vec<PathBuf>>, Error> {
+        let max_files = match &self.config.batch {
+            Some(b) => b.max_files(),
+            None => 1,
+        };
+
+        let mut filtered = Vec::new();
+        for cur_file in files {
+            if self.should_batch(&cur_file)? {
+                filtered.push(cur_file);
+            }
+        }
+
+        Ok(filtered.chunks(max_files).map(|c| c.to_vec()).collect())
+    }
+
+    // whether the file should be added to one of the library's batches
+    fn should_batch(&self, path: &Path) -> Result<bool, Error> {
+        Ok(self.filter(path)?.is_some() && !self.is_processed(path)?)
+    }
+
+    // runs the library's commands once against all the files, which should have already
+    // passed the library's filters
+    fn process_batch(&self, files: &[PathBuf]) -> Result<Summary, Error> {
+        let target = Target::Batch(files);
+        if *self.dry_run != Some(DryRun::Json) {
+            println!("Processing {}", target);
+        }
+        if let Some(d) = self.dry_run {
+            self.print_rendered(*d, &self.render_batch(files)?, Some(files))?;
+            return Ok(Summary::for_target(Summary::processed(), &target));
+        }
+
+        let start = Instant::now();
+        let result = self.templates().and_then(|t| {
+            let variables = self.batch_variables();
+            self.run_steps(&target, t, |o| batch_context(&variables, files, o))
+        });
+        self.record_event(&target, None, &result, start.elapsed());
+        for cur_file in files {
+            self.record_state(cur_file, &result)?;
+        }
+        result
+    }
+
+    // reads the file's current state. The file's contents are only hashed if the library skips
+    // files with unchanged contents. Returns `None` if the file no longer exists
+    fn file_state(&self, path: &Path) -> Result<Option<state::FileState>, Error> {
+        let with_hash = self.config.skip_if_processed == Some(state::SkipIfProcessed::Content);
+        Ok(state::FileState::read(path, with_hash)?)
+    }
+
+    // whether the library should skip the file since it was already processed, and hasn't
+    // changed since, or since it was moved or deleted after it was listed
+    fn is_processed(&self, path: &Path) -> Result<bool, Error> {
+        let (db, policy) = match (self.state, self.config.skip_if_processed) {
+            (Some(d), Some(p)) => (d, p),
+            _ => return Ok(false),
+        };
+        let current = match self.file_state(path)? {
+            Some(c) => c,
+            None => return Ok(true),
+        };
+
+        let processed = match db.get(&self.config.name, path)? {
+            Some(s) => {
+                s.outcome == events::Outcome::Processed.as_str() && s.is_unchanged(&current, policy)
+            }
+            None => false,
+        };
+        if processed && *self.dry_run != Some(DryRun::Json) {
+            println!(
+                "Skipped '{}' in the {} library since it was already processed",
+                path.display(),
+                self.config.name
+            );
         }
-        Ok(num_processed_files)
+        Ok(processed)
     }
 
-    fn process_dir(&self, dir: &Path) -> Result<u64, Error> {
-        let mut num_processed_files: u64 = 0;
-        // iteratively go through all files in directory
-        let paths = fs::read_dir(dir)?;
+    // saves how processing the file ended, together with its state after it was processed, to
+    // the state database. The state is read after processing so that files the library's
+    // commands edit in place are still skipped the next time. Nothing is saved for files the
+    // library moved or deleted, or during dry runs since they don't process anything
+    fn record_state(&self, path: &Path, result: &Result<Summary, Error>) -> Result<(), Error> {
+        let db = match (self.state, self.dry_run) {
+            (Some(d), None) => d,
+            _ => return Ok(()),
+        };
+        let file_state = match self.file_state(path)? {
+            Some(f) => f,
+            None => return Ok(()),
+        };
 
-        for cur_entry_res in paths {
-            let cur_entry = cur_entry_res?;
+        let file_state = state::FileState {
+            outcome: event_outcome(result).0.as_str().to_string(),
+            ..file_state
+        };
+        Ok(db.set(&self.config.name, path, &file_state)?)
+    }
 
-            let file_type = cur_entry.file_type()?;
-            if file_type.is_dir() {
-                num_processed_files += self.process_dir(&cur_entry.path())?;
-            } else if self.process_file(&cur_entry.path())? {
-                num_processed_files += 1;
+    // writes a record of how processing every file in the target ended to the event stream, if
+    // Librarian has one. Failing to write the record is reported but doesn't fail the file
+    fn record_event(
+        &self,
+        target: &Target,
+        mime_type: Option<&str>,
+        result: &Result<Summary, Error>,
+        duration: Duration,
+    ) {
+        let events = match self.events {
+            Some(e) => e,
+            None => return,
+        };
+
+        let (outcome, error) = event_outcome(result);
+        let paths = match target {
+            Target::File(p) => vec![*p],
+            Target::Batch(b) => b.iter().map(|p| p.as_path()).collect(),
+        };
+        for cur_path in paths {
+            let record = events::Record {
+                library: &self.config.name,
+                path: cur_path,
+                mime_type,
+                outcome,
+                duration: duration.as_secs_f64(),
+                error: error.clone(),
+            };
+            if let Err(e) = events.write(&record) {
+                eprintln!("{}", e);
             }
         }
-        Ok(num_processed_files)
     }
 
-    fn process_file(&self, path: &Path) -> Result<bool, Error> {
+    /// Runs the file through the library's filters and, if it passes them, renders the
+    /// library's command against it without running the command
+    pub fn render(&self, path: &Path) -> Result<Option<Rendered>, Error> {
+        let (mime_type, checksums) = match self.filter(path)? {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+
+        if self.config.batch.is_some() {
+            return Ok(Some(self.render_batch(&[path.to_path_buf()])?));
+        }
+        Ok(Some(self.render_command(
+            path,
+            mime_type.as_str(),
+            checksums,
+        )?))
+    }
+
+    // returns the file's MIME type, and any checksums calculated while filtering, if the file
+    // passes the library's filters
+    fn filter(&self, path: &Path) -> Result<Option<(String, Checksums)>, Error> {
         let mime_type = match mime_type::File::new(path).get_mime_type() {
             Err(e) => {
                 eprintln!("{}", e);
-                return Ok(false);
+                return Ok(None);
             }
             Ok(m) => m,
         };
@@ -107,49 +600,577 @@ impl<'a> Library<'a> {
             }
 
             if !is_matched {
-                return Ok(false);
+                return Ok(None);
             }
         }
 
-        // run the command if mime_type passes
-        self.run_command(path, mime_type.as_str())
-    }
-
-    fn run_command(&self, path: &Path, mime_type: &str) -> Result<bool, Error> {
-        println!("Processing '{}'", path.display());
-        if *self.skip_running_commands {
-            match path.as_os_str().to_str() {
-                None => {
-                    return Err(Error::ReadPath(path.into()));
-                }
-                Some(s) => {
-                    println!("{}", s);
-                    return Ok(true);
+        // if a checksum filter is defined, check if the file's checksum is one of the values
+        let mut checksums = HashMap::new();
+        if let Some(checksum_filter) = &self.config.filter.checksum {
+            checksums = match checksum::File::new(path).get_checksums(&[checksum_filter.algorithm])
+            {
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return Ok(None);
                 }
+                Ok(c) => c,
+            };
+
+            let file_checksum = &checksums[&checksum_filter.algorithm];
+            if !checksum_filter
+                .values
+                .iter()
+                .any(|v| v.eq_ignore_ascii_case(file_checksum))
+            {
+                return Ok(None);
             }
         }
 
+        Ok(Some((mime_type, checksums)))
+    }
+
+    // returns the library's templates, compiling them the first time they're needed
+    fn templates(&self) -> Result<&Templates, Error> {
+        if let Some(t) = self.template_cache.get() {
+            return Ok(t);
+        }
+        let templates = self.compile_templates()?;
+        Ok(self.template_cache.get_or_init(|| templates))
+    }
+
+    // returns the templates for the commands, and conditions, of each of the library's steps,
+    // for the directory the commands run in, and for the destination of the library's action
+    fn compile_templates(&self) -> Result<Templates, Error> {
+        let empty_snippets = HashMap::new();
+        let snippets = self
+            .global_config
+            .templates
+            .as_ref()
+            .unwrap_or(&empty_snippets);
+
+        let mut set = template::Set::with_snippets(snippets)?;
+        let mut templates = Templates {
+            steps: Vec::new(),
+            cwd: match &self.config.cwd {
+                Some(c) => Some(set.add(c)?),
+                None => None,
+            },
+            action_dest: match self.config.action.as_ref().and_then(|a| a.dest.as_ref()) {
+                Some(d) => Some(set.add(d)?),
+                None => None,
+            },
+            webhook: match self.config.action.as_ref() {
+                Some(config::Action {
+                    url: Some(u),
+                    body,
+                    headers,
+                    ..
+                }) => Some(WebhookTemplates {
+                    url: set.add(u)?,
+                    body: match body {
+                        Some(b) => Some(set.add(b)?),
+                        None => None,
+                    },
+                    headers: headers
+                        .iter()
+                        .flatten()
+                        .map(|(k, v)| Ok((k.clone(), set.add(v)?)))
+                        .collect::<Result<_, Error>>()?,
+                }),
+                _ => None,
+            },
+            on_success: match &self.config.on_success {
+                Some(h) => Some(set.add(h)?),
+                None => None,
+            },
+            on_failure: match &self.config.on_failure {
+                Some(h) => Some(set.add(h)?),
+                None => None,
+            },
+            set,
+        };
+        for cur_step in self.config.steps() {
+            templates.steps.push(StepTemplates {
+                command: templates.set.add(&cur_step.command)?,
+                when: match cur_step.when {
+                    Some(w) => Some(templates.set.add_condition(w.as_str())?),
+                    None => None,
+                },
+            });
+        }
+
+        Ok(templates)
+    }
+
+    // returns the variables available to the templates of the library's steps
+    fn variables(
+        &self,
+        path: &Path,
+        mime_type: &str,
+        mut checksums: Checksums,
+        templates: &Templates,
+    ) -> Result<BTreeMap<String, String>, Error> {
         let path_str = match path.as_os_str().to_str() {
             None => {
                 return Err(Error::ReadPath(path.into()));
             }
             Some(s) => s,
         };
-        let mut data = collections::HashMap::new();
+
+        // only calculate the checksums referenced in the templates since they require reading
+        // the whole file
+        let missing_checksums: Vec<checksum::Algorithm> = checksum::ALGORITHMS
+            .iter()
+            .filter(|a| !checksums.contains_key(a) && templates.references(a.as_str()))
+            .cloned()
+            .collect();
+        checksums.extend(checksum::File::new(path).get_checksums(&missing_checksums)?);
+
+        let mut data = self.global_config.vars(self.config);
+        for (cur_algorithm, cur_checksum) in checksums.iter() {
+            data.insert(cur_algorithm.as_str(), cur_checksum.as_str());
+        }
         data.insert(TEMPLATE_VAR_FILE_PATH, path_str);
         data.insert(TEMPLATE_VAR_MIME_TYPE, mime_type);
+        data.insert(
+            TEMPLATE_VAR_PARENT_DIR,
+            path.parent().and_then(|p| p.to_str()).unwrap_or_default(),
+        );
 
-        let tmplt = template::Template::new(self.config.command.clone())?;
-        let cmd_str = tmplt.render(&data)?;
+        Ok(data
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect())
+    }
 
-        let output = if env::consts::OS == "windows" {
-            Command::new("cmd").arg("/C").arg(cmd_str).output()
-        } else {
-            Command::new("sh").arg("-c").arg(cmd_str).output()
+    fn render_command(
+        &self,
+        path: &Path,
+        mime_type: &str,
+        checksums: Checksums,
+    ) -> Result<Rendered, Error> {
+        let templates = self.templates()?;
+        let variables = self.variables(path, mime_type, checksums, templates)?;
+        if let Some(a) = self.action(path, templates, &variables)? {
+            return Ok(Rendered {
+                commands: vec![a.to_string()],
+                context: variables,
+            });
+        }
+
+        Ok(Rendered {
+            commands: render_steps(templates, |o| step_context(&variables, o))?,
+            context: variables,
+        })
+    }
+
+    fn render_batch(&self, files: &[PathBuf]) -> Result<Rendered, Error> {
+        let templates = self.templates()?;
+        let variables = self.batch_variables();
+
+        Ok(Rendered {
+            commands: render_steps(templates, |o| batch_context(&variables, files, o))?,
+            context: variables,
+        })
+    }
+
+    // returns the variables, other than the list of files, available to the templates of a
+    // batch library's steps
+    fn batch_variables(&self) -> BTreeMap<String, String> {
+        self.global_config
+            .vars(self.config)
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn print_rendered(
+        &self,
+        dry_run: DryRun,
+        rendered: &Rendered,
+        files: Option<&[PathBuf]>,
+    ) -> Result<(), Error> {
+        match dry_run {
+            DryRun::Text => {
+                for cur_command in rendered.commands.iter() {
+                    println!("{}", cur_command);
+                }
+            }
+            DryRun::Json => {
+                let mut json = serde_json::json!({
+                    "library": self.config.name,
+                    "commands": rendered.commands,
+                    "context": rendered.context,
+                });
+                if let Some(f) = files {
+                    json[TEMPLATE_VAR_FILES] = serde_json::json!(f);
+                }
+                println!("{}", serde_json::to_string(&json)?);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_command(
+        &self,
+        path: &Path,
+        mime_type: &str,
+        checksums: Checksums,
+    ) -> Result<Summary, Error> {
+        if *self.dry_run != Some(DryRun::Json) {
+            println!("Processing '{}'", path.display());
+        }
+        if let Some(d) = self.dry_run {
+            let rendered = self.render_command(path, mime_type, checksums)?;
+            self.print_rendered(*d, &rendered, None)?;
+            return Ok(Summary::processed());
+        }
+
+        let templates = self.templates()?;
+        let variables = self.variables(path, mime_type, checksums, templates)?;
+        if let Some(a) = self.action(path, templates, &variables)? {
+            let start = Instant::now();
+            let result = a.run(&mut Vec::new());
+            let outcome = Outcome {
+                exit_code: Some(if result.is_ok() { 0 } else { 1 }),
+                stderr: "",
+                duration: start.elapsed(),
+            };
+            let context = step_context(&variables, &[]);
+            return match result {
+                Ok(action::Outcome::Done) => {
+                    self.run_hook(
+                        &templates.on_success,
+                        &Target::File(path),
+                        templates,
+                        context,
+                        &outcome,
+                    );
+                    Ok(Summary::processed())
+                }
+                Ok(action::Outcome::Deduplicated) => {
+                    self.run_hook(
+                        &templates.on_success,
+                        &Target::File(path),
+                        templates,
+                        context,
+                        &outcome,
+                    );
+                    println!(
+                        "'{}' is identical to its destination in the {} library",
+                        path.display(),
+                        self.config.name
+                    );
+                    Ok(Summary::processed())
+                }
+                Ok(action::Outcome::Skipped) => {
+                    println!(
+                        "Skipped '{}' in the {} library since its destination already exists",
+                        path.display(),
+                        self.config.name
+                    );
+                    Ok(Summary::default())
+                }
+                Err(e) => {
+                    let details = match e.source() {
+                        Some(s) => format!("{}: {}", e, s),
+                        None => e.to_string(),
+                    };
+                    let outcome = Outcome {
+                        stderr: &details,
+                        ..outcome
+                    };
+                    self.run_hook(
+                        &templates.on_failure,
+                        &Target::File(path),
+                        templates,
+                        context,
+                        &outcome,
+                    );
+                    self.on_failure(&Target::File(path), "failed".to_string(), &details)
+                }
+            };
+        }
+
+        self.run_steps(&Target::File(path), templates, |o| {
+            step_context(&variables, o)
+        })
+    }
+
+    // runs the steps one after the other, stopping at the first one that fails
+    fn run_steps<F>(
+        &self,
+        target: &Target,
+        templates: &Templates,
+        context: F,
+    ) -> Result<Summary, Error>
+    where
+        F: Fn(&[StepOutput]) -> tera::Context,
+    {
+        let start = Instant::now();
+        let mut step_outputs = Vec::new();
+        let mut last_output = None;
+        for (i, cur_templates) in templates.steps.iter().enumerate() {
+            let context = context(&step_outputs);
+            if !cur_templates.should_run(&templates.set, &context)? {
+                step_outputs.push(StepOutput::skipped());
+                continue;
+            }
+
+            let cmd = templates.set.render(&cur_templates.command, &context)?;
+            let cwd = match &templates.cwd {
+                Some(c) => Some(PathBuf::from(templates.set.render(c, &context)?)),
+                None => None,
+            };
+            let stdin = match (self.config.stdin, target) {
+                (Some(config::Stdin::File), Target::File(p)) => {
+                    command::Stdin::File(p.to_path_buf())
+                }
+                (Some(config::Stdin::Json), _) => {
+                    command::Stdin::Data(serde_json::to_vec(&context.clone().into_json())?)
+                }
+                _ => command::Stdin::Null,
+            };
+            let output = self.run_with_retries(target, &cmd, cwd, stdin)?;
+            if !output.success() {
+                let mut failure = if output.timed_out {
+                    format!(
+                        "timed out after {} seconds",
+                        self.config.timeout.unwrap_or_default()
+                    )
+                } else {
+                    format!("failed with exit code {}", exit_code_str(output.exit_code))
+                };
+                if templates.steps.len() > 1 {
+                    failure = format!("{} in step {}", failure, i);
+                }
+                let outcome = Outcome {
+                    exit_code: output.exit_code,
+                    stderr: &output.stderr,
+                    duration: start.elapsed(),
+                };
+                self.run_hook(&templates.on_failure, target, templates, context, &outcome);
+                return self.on_failure(target, failure, output.stderr.trim_end());
+            }
+            step_outputs.push(StepOutput::from(&output));
+            last_output = Some(output);
+        }
+
+        let outcome = Outcome {
+            exit_code: Some(0),
+            stderr: last_output
+                .as_ref()
+                .map(|o| o.stderr.as_str())
+                .unwrap_or_default(),
+            duration: start.elapsed(),
+        };
+        self.run_hook(
+            &templates.on_success,
+            target,
+            templates,
+            context(&step_outputs),
+            &outcome,
+        );
+
+        Ok(Summary::for_target(Summary::processed(), target))
+    }
+
+    // runs the hook, if the library has it, with the outcome of the library's commands added to
+    // the context. A hook that fails, including to render or start, is reported but doesn't
+    // change the file's outcome
+    fn run_hook(
+        &self,
+        hook: &Option<template::Template>,
+        target: &Target,
+        templates: &Templates,
+        context: tera::Context,
+        outcome: &Outcome,
+    ) {
+        if let Err(e) = self.try_run_hook(hook, target, templates, context, outcome) {
+            let details = match e.source() {
+                Some(s) => format!("{}: {}", e, s),
+                None => e.to_string(),
+            };
+            eprintln!(
+                "A hook in the {} library failed: {}",
+                self.config.name, details
+            );
+        }
+    }
+
+    fn try_run_hook(
+        &self,
+        hook: &Option<template::Template>,
+        target: &Target,
+        templates: &Templates,
+        mut context: tera::Context,
+        outcome: &Outcome,
+    ) -> Result<(), Error> {
+        let hook = match hook {
+            Some(h) => h,
+            None => return Ok(()),
         };
-        output?;
+        context.insert(TEMPLATE_VAR_EXIT_CODE, &outcome.exit_code);
+        context.insert(TEMPLATE_VAR_STDERR, outcome.stderr);
+        context.insert(TEMPLATE_VAR_DURATION, &outcome.duration.as_secs_f64());
 
-        Ok(true)
+        let cwd = match &templates.cwd {
+            Some(c) => Some(PathBuf::from(templates.set.render(c, &context)?)),
+            None => None,
+        };
+        let cmd = templates.set.render(hook, &context)?;
+        let output = self.command(cmd.clone(), cwd).run()?;
+        self.log(target, &cmd, &output);
+        if !output.success() {
+            eprintln!(
+                "A hook in the {} library failed with exit code {}: {}",
+                self.config.name,
+                exit_code_str(output.exit_code),
+                output.stderr.trim_end()
+            );
+        }
+
+        Ok(())
+    }
+
+    // reports the failure, and decides whether the library's other files should still be
+    // processed
+    fn on_failure(
+        &self,
+        target: &Target,
+        failure: String,
+        details: &str,
+    ) -> Result<Summary, Error> {
+        eprintln!(
+            "The command for {} in the {} library {}: {}",
+            target, self.config.name, failure, details
+        );
+        match self.config.on_error {
+            Some(config::OnError::Abort) => Err(Error::CommandFailed(target.to_string(), failure)),
+            Some(config::OnError::Continue) | None => {
+                Ok(Summary::for_target(Summary::failed(), target))
+            }
+        }
+    }
+
+    // returns the library's action for the file, with its destination rendered, if the library
+    // uses an action instead of commands
+    fn action<'b>(
+        &self,
+        path: &'b Path,
+        templates: &Templates,
+        variables: &BTreeMap<String, String>,
+    ) -> Result<Option<action::Action<'b>>, Error>
+    where
+        'a: 'b,
+    {
+        let config = match &self.config.action {
+            Some(a) => a,
+            None => return Ok(None),
+        };
+        let context = step_context(variables, &[]);
+        let dest = match &templates.action_dest {
+            Some(d) => Some(PathBuf::from(templates.set.render(d, &context)?)),
+            None => None,
+        };
+        let webhook = match &templates.webhook {
+            Some(w) => Some(
+                w.render(&templates.set, variables, &context)?
+                    .timeout(self.config.timeout.map(Duration::from_secs))
+                    .retries(
+                        self.config.retries.unwrap_or(0),
+                        Duration::from_secs(
+                            self.config.retry_backoff.unwrap_or(DEFAULT_RETRY_BACKOFF),
+                        ),
+                    ),
+            ),
+            None => None,
+        };
+
+        Ok(Some(
+            action::Action::new(config.operation, path, dest)
+                .on_conflict(config.on_conflict.unwrap_or_default())
+                .trash(self.journal.map(|j| j.trash_dir()))
+                .journal(self.journal, &self.config.name)
+                .webhook(webhook),
+        ))
+    }
+
+    // runs the command, retrying it with an exponential backoff if it fails in a way the
+    // library considers retryable
+    fn run_with_retries(
+        &self,
+        target: &Target,
+        cmd: &str,
+        cwd: Option<PathBuf>,
+        stdin: command::Stdin,
+    ) -> Result<command::Output, Error> {
+        let retries = self.config.retries.unwrap_or(0);
+        let backoff =
+            Duration::from_secs(self.config.retry_backoff.unwrap_or(DEFAULT_RETRY_BACKOFF));
+        let mut attempt: u32 = 0;
+        loop {
+            let output = self
+                .command(cmd.to_string(), cwd.clone())
+                .stdin(stdin.clone())
+                .run()?;
+            self.log(target, cmd, &output);
+            if output.success() || attempt >= retries || !self.is_retryable(&output) {
+                return Ok(output);
+            }
+
+            let cur_backoff = backoff
+                .checked_mul(2u32.saturating_pow(attempt))
+                .unwrap_or(Duration::MAX);
+            eprintln!(
+                "The command for {} in the {} library failed, retrying in {:?}",
+                target, self.config.name, cur_backoff
+            );
+            thread::sleep(cur_backoff);
+            attempt += 1;
+        }
+    }
+
+    // returns the command, set up to run the way the library's config says commands should
+    fn command(&self, cmd: String, cwd: Option<PathBuf>) -> command::Command {
+        command::Command::new(cmd)
+            .timeout(self.config.timeout.map(Duration::from_secs))
+            .shell(self.config.shell.clone())
+            .env(self.config.env.clone().unwrap_or_default())
+            .clear_env(self.config.clear_env.unwrap_or(false))
+            .cwd(cwd)
+            .limits(self.config.limits())
+            .run_as(self.config.run_as.clone())
+            .sandbox(self.config.sandbox())
+    }
+
+    // appends the command's output to the library's log, if it has one. Failing to write to the
+    // log is reported but doesn't fail the command
+    fn log(&self, target: &Target, cmd: &str, output: &command::Output) {
+        let config = match &self.config.log {
+            Some(l) => l,
+            None => return,
+        };
+
+        let log = command_log::Log::new(
+            Path::new(&config.path),
+            config.max_bytes(),
+            config.max_files(),
+        );
+        if let Err(e) = log.write(&target.to_string(), cmd, output) {
+            eprintln!("{}", e);
+        }
+    }
+
+    fn is_retryable(&self, output: &command::Output) -> bool {
+        match &self.config.retry_exit_codes {
+            None => true,
+            Some(codes) => match output.exit_code {
+                Some(c) => !output.timed_out && codes.contains(&c),
+                None => false,
+            },
+        }
     }
 
     pub fn contains_path(&self, path: &Path) -> bool {
@@ -186,3 +1207,201 @@ impl<'a> Library<'a> {
         false
     }
 }
+
+fn step_context(
+    variables: &BTreeMap<String, String>,
+    step_outputs: &[StepOutput],
+) -> tera::Context {
+    let mut context = tera::Context::new();
+    for (cur_key, cur_val) in variables.iter() {
+        context.insert(cur_key, cur_val);
+    }
+    context.insert(TEMPLATE_VAR_STEPS, step_outputs);
+
+    context
+}
+
+// returns the context for a batch library's templates, with the list of files and the outputs
+// of the steps that have already run
+fn batch_context(
+    variables: &BTreeMap<String, String>,
+    files: &[PathBuf],
+    step_outputs: &[StepOutput],
+) -> tera::Context {
+    let mut context = step_context(variables, step_outputs);
+    let files: Vec<String> = files
+        .iter()
+        .map(|f| f.to_string_lossy().to_string())
+        .collect();
+    context.insert(TEMPLATE_VAR_FILES, &files);
+
+    context
+}
+
+// renders the commands of the steps that would run. The steps' outputs aren't known without
+// running them so placeholders are used instead
+fn render_steps<F>(templates: &Templates, context: F) -> Result<Vec<String>, Error>
+where
+    F: Fn(&[StepOutput]) -> tera::Context,
+{
+    let mut step_outputs = Vec::new();
+    let mut commands = Vec::new();
+    for (i, cur_templates) in templates.steps.iter().enumerate() {
+        let context = context(&step_outputs);
+        if cur_templates.should_run(&templates.set, &context)? {
+            commands.push(templates.set.render(&cur_templates.command, &context)?);
+            step_outputs.push(StepOutput::placeholder(i));
+        } else {
+            step_outputs.push(StepOutput::skipped());
+        }
+    }
+
+    Ok(commands)
+}
+
+/// Processes the library's files, like `Library::process`, but runs the filters and commands
+/// for the files in the worker pool. At most the library's configured concurrency of files
+/// are processed at the same time
+pub fn process_with_pool(
+    global_config: &Arc<config::Config>,
+    name: &str,
+    dry_run: Option<DryRun>,
+    path: Option<&Path>,
+    pool: &worker_pool::WorkerPool,
+    records: &Records,
+) -> Result<Summary, Error> {
+    let config = match global_config.libraries.get(name) {
+        Some(c) => c,
+        None => return Ok(Summary::default()),
+    };
+    let lib = Library::new(global_config, config, &dry_run);
+    let files = lib.files(path)?;
+    let concurrency = config.concurrency();
+    let template_cache = Arc::new(OnceLock::new());
+    // every job runs the library's commands against a single file, or against a batch of files
+    let jobs: Vec<Vec<PathBuf>> = match &config.batch {
+        Some(b) => {
+            let files = filter_with_pool(global_config, name, dry_run, files, pool, records)?;
+            files.chunks(b.max_files()).map(|c| c.to_vec()).collect()
+        }
+        None => files.into_iter().map(|f| vec![f]).collect(),
+    };
+
+    // set once a file fails in a way that should stop the library's other files from being
+    // processed
+    let aborted = Arc::new(AtomicBool::new(false));
+    let (result_sender, result_receiver) = channel();
+    for cur_files in jobs {
+        let global_config = Arc::clone(global_config);
+        let name = name.to_string();
+        let aborted = Arc::clone(&aborted);
+        let result_sender = result_sender.clone();
+        let records = records.clone();
+        let template_cache = Arc::clone(&template_cache);
+        pool.execute(name.clone().as_str(), concurrency, move || {
+            if aborted.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let lib = Library::new(&global_config, &global_config.libraries[&name], &dry_run)
+                .records(&records)
+                .template_cache(&template_cache);
+            let result = if lib.config.batch.is_some() {
+                lib.process_batch(&cur_files)
+            } else {
+                lib.process_file(&cur_files[0])
+            };
+            if result.is_err() {
+                aborted.store(true, Ordering::SeqCst);
+            }
+            let _ = result_sender.send(result);
+        });
+    }
+    drop(result_sender);
+
+    let mut summary = Summary::default();
+    let mut first_err = None;
+    for cur_result in result_receiver {
+        match cur_result {
+            Ok(s) => summary += s,
+            Err(e) => {
+                first_err.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_err {
+        Some(e) => Err(e),
+        None => Ok(summary),
+    }
+}
+
+// returns the files that should be added to the library's batches, in the order they were
+// listed. The files are filtered in the worker pool, like files that aren't batched are, so
+// that slow filters, like checksums, don't run one file at a time
+fn filter_with_pool(
+    global_config: &Arc<config::Config>,
+    name: &str,
+    dry_run: Option<DryRun>,
+    files: Vec<PathBuf>,
+    pool: &worker_pool::WorkerPool,
+    records: &Records,
+) -> Result<Vec<PathBuf>, Error> {
+    let concurrency = global_config.libraries[name].concurrency();
+    let (result_sender, result_receiver) = channel();
+    for (i, cur_file) in files.into_iter().enumerate() {
+        let global_config = Arc::clone(global_config);
+        let name = name.to_string();
+        let result_sender = result_sender.clone();
+        let records = records.clone();
+        pool.execute(name.clone().as_str(), concurrency, move || {
+            let lib = Library::new(&global_config, &global_config.libraries[&name], &dry_run)
+                .records(&records);
+            let result = lib.should_batch(&cur_file).map(|b| b.then_some(cur_file));
+            let _ = result_sender.send((i, result));
+        });
+    }
+    drop(result_sender);
+
+    let mut filtered = Vec::new();
+    for (i, cur_result) in result_receiver {
+        if let Some(f) = cur_result? {
+            filtered.push((i, f));
+        }
+    }
+    filtered.sort_by_key(|(i, _)| *i);
+    Ok(filtered.into_iter().map(|(_, f)| f).collect())
+}
+
+// returns how processing the files ended, and the error, if there was one, that stopped them
+// from being processed
+fn event_outcome(result: &Result<Summary, Error>) -> (events::Outcome, Option<String>) {
+    match result {
+        Ok(s) if s.failed > 0 => (events::Outcome::Failed, None),
+        Ok(s) if s.processed > 0 => (events::Outcome::Processed, None),
+        Ok(_) => (events::Outcome::Skipped, None),
+        Err(e) => (events::Outcome::Failed, Some(e.to_string())),
+    }
+}
+
+// iteratively adds all the files in the directory, and its sub-directories, to the list
+fn list_dir(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+    for cur_entry_res in fs::read_dir(dir)? {
+        let cur_entry = cur_entry_res?;
+
+        if cur_entry.file_type()?.is_dir() {
+            list_dir(&cur_entry.path(), files)?;
+        } else {
+            files.push(cur_entry.path());
+        }
+    }
+
+    Ok(())
+}
+
+fn exit_code_str(exit_code: Option<i32>) -> String {
+    match exit_code {
+        Some(c) => c.to_string(),
+        None => "none (terminated by a signal)".to_string(),
+    }
+}
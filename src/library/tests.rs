@@ -28,22 +28,37 @@ fn test_process() {
             "s a video. The file's MIME type is".to_string(),
         ),
     );
+    test_cases.insert(
+        "images".to_string(),
+        (
+            vec!["images_tests_files_image_png".to_string()],
+            "is an image. The file's MIME type is image/png".to_string(),
+        ),
+    );
+    test_cases.insert(
+        "checksums".to_string(),
+        (
+            vec!["checksums_tests_files_text_plain".to_string()],
+            "has the MD5 checksum f475529ca97ec7cf784c39975fc631b2".to_string(),
+        ),
+    );
 
-    for (cur_lib_key, cur_lib_val) in conf.unwrap().libraries {
-        if !test_cases.contains_key(&cur_lib_key) {
+    let conf = conf.unwrap();
+    for (cur_lib_key, cur_lib_val) in conf.libraries.iter() {
+        if !test_cases.contains_key(cur_lib_key) {
             continue;
         }
-        let skip_running_commands = false;
-        let lib = Library::new(&cur_lib_val, &skip_running_commands);
+        let dry_run = None;
+        let lib = Library::new(&conf, cur_lib_val, &dry_run);
         assert_eq!(
-            lib.process(None).unwrap(),
-            test_cases.get(&cur_lib_key).unwrap().0.len() as u64
+            lib.process(None).unwrap().processed,
+            test_cases.get(cur_lib_key).unwrap().0.len() as u64
         );
 
-        for cur_path in test_cases.get(&cur_lib_key).unwrap().0.iter() {
+        for cur_path in test_cases.get(cur_lib_key).unwrap().0.iter() {
             let path_buf: PathBuf = ["tests", "tmp", cur_path].iter().collect();
             let contents = fs::read_to_string(path_buf.as_path()).unwrap();
-            assert!(contents.contains(test_cases.get(&cur_lib_key).unwrap().1.as_str()));
+            assert!(contents.contains(test_cases.get(cur_lib_key).unwrap().1.as_str()));
         }
     }
 }
@@ -92,20 +107,21 @@ fn test_process_single_file() {
         );
     }
 
-    let skip_running_commands = false;
-    for (cur_lib_key, cur_lib_val) in conf.unwrap().libraries {
-        if !test_cases.contains_key(&cur_lib_key) {
+    let dry_run = None;
+    let conf = conf.unwrap();
+    for (cur_lib_key, cur_lib_val) in conf.libraries.iter() {
+        if !test_cases.contains_key(cur_lib_key) {
             continue;
         }
-        let lib = Library::new(&cur_lib_val, &skip_running_commands);
+        let lib = Library::new(&conf, cur_lib_val, &dry_run);
 
-        for cur_file in test_cases.get(&cur_lib_key).unwrap().0.iter() {
+        for cur_file in test_cases.get(cur_lib_key).unwrap().0.iter() {
             let cur_test_path = Path::new(cur_file.0.as_str());
-            assert_eq!(lib.process(Some(cur_test_path)).unwrap(), 1u64);
+            assert_eq!(lib.process(Some(cur_test_path)).unwrap().processed, 1u64);
             let cur_generated_path: PathBuf =
                 ["tests", "tmp", cur_file.1.as_str()].iter().collect();
             let contents = fs::read_to_string(cur_generated_path.as_path()).unwrap();
-            assert!(contents.contains(test_cases.get(&cur_lib_key).unwrap().1.as_str()));
+            assert!(contents.contains(test_cases.get(cur_lib_key).unwrap().1.as_str()));
         }
     }
 }
@@ -118,8 +134,8 @@ fn test_contains_path() {
         config::Config::new(&"tests/configs/good.toml".to_string()).unwrap()
     };
     let cur_dir = current_dir().unwrap();
-    let skip_running_commands = false;
-    let audio_lib = Library::new(&conf.libraries["audio"], &skip_running_commands);
+    let dry_run = None;
+    let audio_lib = Library::new(&conf, &conf.libraries["audio"], &dry_run);
     assert!(!audio_lib.contains_path(Path::new("")));
     if OS == "windows" {
         assert!(audio_lib.contains_path(Path::new("tests\\files\\audio\\flac")));
@@ -143,3 +159,722 @@ fn test_contains_path() {
         )));
     }
 }
+
+#[test]
+fn test_render() {
+    let conf = if OS == "windows" {
+        config::Config::new(&"tests\\configs\\good-windows.toml".to_string()).unwrap()
+    } else {
+        config::Config::new(&"tests/configs/good.toml".to_string()).unwrap()
+    };
+    let dry_run = Some(DryRun::Text);
+    let books_lib = Library::new(&conf, &conf.libraries["books"], &dry_run);
+    let plain_path: PathBuf = ["tests", "files", "text", "plain"].iter().collect();
+
+    let rendered = books_lib.render(plain_path.as_path()).unwrap().unwrap();
+    assert_eq!(rendered.commands.len(), 1);
+    assert!(rendered.commands[0].contains("is a book. The file's MIME type is text/plain"));
+    assert_eq!(
+        rendered.context["file_path"],
+        plain_path.as_os_str().to_str().unwrap()
+    );
+    assert_eq!(rendered.context["mime_type"], "text/plain");
+    assert!(rendered.context.contains_key("tmp_dir"));
+    assert!(!rendered.context.contains_key("sha256"));
+
+    let audio_lib = Library::new(&conf, &conf.libraries["audio"], &dry_run);
+    assert!(audio_lib.render(plain_path.as_path()).unwrap().is_none());
+}
+
+#[test]
+fn test_process_dry_run() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.dry_run]
+        command = "echo '{{ file_path }}' > tests/tmp/dry_run_output"
+
+          [libraries.dry_run.filter]
+          directories = [ "tests/files/text" ]
+
+        [libraries.broken]
+        command = "echo '{{ undefined_var }}'"
+
+          [libraries.broken.filter]
+          directories = [ "tests/files/text" ]
+        "#,
+    )
+    .unwrap();
+
+    for cur_dry_run in [Some(DryRun::Text), Some(DryRun::Json)] {
+        let dry_run_lib = Library::new(&conf, &conf.libraries["dry_run"], &cur_dry_run);
+        assert_eq!(dry_run_lib.process(None).unwrap().processed, 2);
+        let output_path: PathBuf = ["tests", "tmp", "dry_run_output"].iter().collect();
+        assert!(!output_path.exists());
+
+        let broken_lib = Library::new(&conf, &conf.libraries["broken"], &cur_dry_run);
+        assert!(broken_lib
+            .process(None)
+            .unwrap_err()
+            .to_string()
+            .contains("A templating error was thrown"));
+    }
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_failing_command() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.continue]
+        command = "test '{{ mime_type }}' = 'text/plain'"
+
+          [libraries.continue.filter]
+          directories = [ "tests/files/text" ]
+
+        [libraries.abort]
+        command = "exit 2"
+        on_error = "abort"
+
+          [libraries.abort.filter]
+          directories = [ "tests/files/text" ]
+
+        [libraries.timeout]
+        command = "sleep 30"
+        timeout = 1
+        on_error = "abort"
+
+          [libraries.timeout.filter]
+          directories = [ "tests/files/text" ]
+        "#,
+    )
+    .unwrap();
+    let dry_run = None;
+
+    let continue_lib = Library::new(&conf, &conf.libraries["continue"], &dry_run);
+    assert_eq!(
+        continue_lib.process(None).unwrap(),
+        Summary {
+            processed: 1,
+            failed: 1
+        }
+    );
+
+    let abort_lib = Library::new(&conf, &conf.libraries["abort"], &dry_run);
+    assert!(abort_lib
+        .process(None)
+        .unwrap_err()
+        .to_string()
+        .contains("failed with exit code 2"));
+
+    let timeout_lib = Library::new(&conf, &conf.libraries["timeout"], &dry_run);
+    assert!(timeout_lib
+        .process(None)
+        .unwrap_err()
+        .to_string()
+        .contains("timed out after 1 seconds"));
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_retries() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.retried]
+        command = """
+        echo attempt >> tests/tmp/retried_attempts
+        test "$(wc -l < tests/tmp/retried_attempts)" -ge 3
+        """
+        retries = 2
+        retry_backoff = 0
+
+          [libraries.retried.filter]
+          directories = [ "tests/files/text" ]
+          mime_type_regexes = [ "text/plain" ]
+
+        [libraries.not_retried]
+        command = """
+        echo attempt >> tests/tmp/not_retried_attempts
+        exit 1
+        """
+        retries = 2
+        retry_backoff = 0
+        retry_exit_codes = [ 75 ]
+
+          [libraries.not_retried.filter]
+          directories = [ "tests/files/text" ]
+          mime_type_regexes = [ "text/plain" ]
+        "#,
+    )
+    .unwrap();
+    let dry_run = None;
+    let test_cases = [
+        (
+            "retried",
+            "retried_attempts",
+            3,
+            Summary {
+                processed: 1,
+                failed: 0,
+            },
+        ),
+        (
+            "not_retried",
+            "not_retried_attempts",
+            1,
+            Summary {
+                processed: 0,
+                failed: 1,
+            },
+        ),
+    ];
+
+    for cur_case in test_cases.iter() {
+        let attempts_path: PathBuf = ["tests", "tmp", cur_case.1].iter().collect();
+        let _ = fs::remove_file(attempts_path.as_path());
+
+        let lib = Library::new(&conf, &conf.libraries[cur_case.0], &dry_run);
+        assert_eq!(lib.process(None).unwrap(), cur_case.3);
+        let attempts = fs::read_to_string(attempts_path.as_path()).unwrap();
+        assert_eq!(attempts.lines().count(), cur_case.2);
+    }
+}
+
+#[test]
+fn test_process_with_pool() {
+    let conf = if OS == "windows" {
+        config::Config::new(&"tests\\configs\\good-windows.toml".to_string())
+    } else {
+        config::Config::new(&"tests/configs/good.toml".to_string())
+    };
+    let conf = Arc::new(conf.unwrap());
+    let pool = worker_pool::WorkerPool::new(2);
+
+    for cur_lib_name in conf.libraries.keys() {
+        let dry_run = Some(DryRun::Text);
+        let expected = Library::new(&conf, &conf.libraries[cur_lib_name], &dry_run)
+            .process(None)
+            .unwrap();
+        assert_eq!(
+            process_with_pool(
+                &conf,
+                cur_lib_name,
+                dry_run,
+                None,
+                &pool,
+                &Records::default()
+            )
+            .unwrap(),
+            expected
+        );
+    }
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_steps() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.steps]
+
+          [[libraries.steps.steps]]
+          command = "printf 'books'"
+
+          [[libraries.steps.steps]]
+          command = "echo 'should not run' > tests/tmp/steps_skipped"
+          when = "mime_type != 'text/plain'"
+
+          [[libraries.steps.steps]]
+          command = "echo '{{ steps.0.stdout }} {{ steps.1.skipped }}' > tests/tmp/steps_output"
+
+          [libraries.steps.filter]
+          directories = [ "tests/files/text" ]
+          mime_type_regexes = [ "text/plain" ]
+
+        [libraries.failing_steps]
+
+          [[libraries.failing_steps.steps]]
+          command = "exit 4"
+
+          [[libraries.failing_steps.steps]]
+          command = "echo 'should not run' > tests/tmp/failing_steps_output"
+
+          [libraries.failing_steps.filter]
+          directories = [ "tests/files/text" ]
+          mime_type_regexes = [ "text/plain" ]
+        "#,
+    )
+    .unwrap();
+    let dry_run = None;
+
+    let steps_lib = Library::new(&conf, &conf.libraries["steps"], &dry_run);
+    assert_eq!(steps_lib.process(None).unwrap().processed, 1);
+    let output_path: PathBuf = ["tests", "tmp", "steps_output"].iter().collect();
+    assert_eq!(
+        fs::read_to_string(output_path.as_path()).unwrap(),
+        "books true\n"
+    );
+    let skipped_path: PathBuf = ["tests", "tmp", "steps_skipped"].iter().collect();
+    assert!(!skipped_path.exists());
+
+    let rendered = steps_lib
+        .render(Path::new("tests/files/text/plain"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(rendered.commands.len(), 2);
+    assert!(rendered.commands[1].contains("<stdout of step 0> true"));
+
+    let failing_lib = Library::new(&conf, &conf.libraries["failing_steps"], &dry_run);
+    assert_eq!(failing_lib.process(None).unwrap().failed, 1);
+    let failing_output_path: PathBuf = ["tests", "tmp", "failing_steps_output"].iter().collect();
+    assert!(!failing_output_path.exists());
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_command_environment() {
+    let tmp_dir = current_dir().unwrap().join("tests").join("tmp");
+    let conf: config::Config = toml::from_str(
+        format!(
+            r#"
+            [libraries.environment]
+            command = "echo \"$LIBRARY_NAME $(basename \"$PWD\") ${{HOME:-unset}}\" > {}/environment_output"
+            cwd = "{{{{ parent_dir }}}}"
+            shell = [ "bash", "-c" ]
+            clear_env = true
+
+              [libraries.environment.env]
+              LIBRARY_NAME = "environment"
+
+              [libraries.environment.filter]
+              directories = [ "tests/files/text" ]
+              mime_type_regexes = [ "text/plain" ]
+            "#,
+            tmp_dir.display()
+        )
+        .as_str(),
+    )
+    .unwrap();
+    let dry_run = None;
+
+    let lib = Library::new(&conf, &conf.libraries["environment"], &dry_run);
+    assert_eq!(lib.process(None).unwrap().processed, 1);
+    assert_eq!(
+        fs::read_to_string(tmp_dir.join("environment_output")).unwrap(),
+        "environment text unset\n"
+    );
+}
+
+#[test]
+fn test_process_action() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.copy]
+        action = { type = "copy", dest = "tests/tmp/action_copies/{{ sha256 }}.txt" }
+
+          [libraries.copy.filter]
+          directories = [ "tests/files/text" ]
+
+        [libraries.broken]
+        action = { type = "copy", dest = "{{ file_path }}/not_a_directory" }
+
+          [libraries.broken.filter]
+          directories = [ "tests/files/text" ]
+        "#,
+    )
+    .unwrap();
+    let dry_run = None;
+    let copies_dir: PathBuf = ["tests", "tmp", "action_copies"].iter().collect();
+    let _ = fs::remove_dir_all(&copies_dir);
+
+    let copy_lib = Library::new(&conf, &conf.libraries["copy"], &dry_run);
+    let plain_path: PathBuf = ["tests", "files", "text", "plain"].iter().collect();
+    let rendered = copy_lib.render(&plain_path).unwrap().unwrap();
+    assert_eq!(rendered.commands.len(), 1);
+    assert!(rendered.commands[0].starts_with(&format!("copy '{}' to '", plain_path.display())));
+    assert!(!copies_dir.exists());
+
+    assert_eq!(copy_lib.process(None).unwrap().processed, 2);
+    assert_eq!(fs::read_dir(&copies_dir).unwrap().count(), 2);
+
+    let broken_lib = Library::new(&conf, &conf.libraries["broken"], &dry_run);
+    let summary = broken_lib.process(None).unwrap();
+    assert_eq!(summary.processed, 0);
+    assert_eq!(summary.failed, 2);
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_events() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.text]
+        command = "test '{{ mime_type }}' = text/plain"
+
+          [libraries.text.filter]
+          directories = [ "tests/files/text" ]
+        "#,
+    )
+    .unwrap();
+    let events_path: PathBuf = ["tests", "tmp", "library_events.ndjson"].iter().collect();
+    let _ = fs::remove_file(&events_path);
+    let events = events::Sink::open(&events_path).unwrap();
+
+    let dry_run = None;
+    let lib = Library::new(&conf, &conf.libraries["text"], &dry_run).events(Some(&events));
+    let summary = lib.process(None).unwrap();
+    assert_eq!(summary.processed, 1);
+    assert_eq!(summary.failed, 1);
+
+    let mut outcomes: Vec<(String, String)> = fs::read_to_string(&events_path)
+        .unwrap()
+        .lines()
+        .map(|l| {
+            let record: serde_json::Value = serde_json::from_str(l).unwrap();
+            (
+                record["mime_type"].as_str().unwrap().to_string(),
+                record["outcome"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    outcomes.sort();
+    assert_eq!(
+        outcomes,
+        vec![
+            ("application/pdf".to_string(), "failed".to_string()),
+            ("text/plain".to_string(), "processed".to_string()),
+        ]
+    );
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_skip_if_processed() {
+    let dir: PathBuf = ["tests", "tmp", "skip_if_processed"].iter().collect();
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("files")).unwrap();
+    fs::write(dir.join("files/a.txt"), "a").unwrap();
+    fs::write(dir.join("files/b.txt"), "b").unwrap();
+    let mut conf: config::Config = toml::from_str(
+        r#"
+        [libraries.metadata]
+        command = "echo '{{ file_path }}' >> tests/tmp/skip_if_processed/metadata_output"
+        skip_if_processed = "metadata"
+
+          [libraries.metadata.filter]
+          directories = [ "tests/tmp/skip_if_processed/files" ]
+
+        [libraries.content]
+        command = "echo '{{ file_path }}' >> tests/tmp/skip_if_processed/content_output"
+        skip_if_processed = "content"
+
+          [libraries.content.filter]
+          directories = [ "tests/tmp/skip_if_processed/files" ]
+        "#,
+    )
+    .unwrap();
+    // the libraries' state is stored by their names, which are set when the config is read from
+    // a file
+    for (cur_name, cur_lib) in conf.libraries.iter_mut() {
+        cur_lib.name = cur_name.clone();
+    }
+    let db = state::State::open(&dir.join("state.db")).unwrap();
+    let dry_run = None;
+    let process = |name: &str| {
+        Library::new(&conf, &conf.libraries[name], &dry_run)
+            .state(Some(&db))
+            .process(None)
+            .unwrap()
+            .processed
+    };
+    let output_lines = |name: &str| {
+        fs::read_to_string(dir.join(format!("{}_output", name)))
+            .unwrap()
+            .lines()
+            .count()
+    };
+
+    for cur_lib in ["metadata", "content"] {
+        assert_eq!(process(cur_lib), 2);
+        assert_eq!(process(cur_lib), 0);
+        assert_eq!(output_lines(cur_lib), 2);
+    }
+
+    // rewriting a file with the same contents only changes its metadata
+    fs::remove_file(dir.join("files/a.txt")).unwrap();
+    fs::write(dir.join("files/a.txt"), "a").unwrap();
+    assert_eq!(process("metadata"), 1);
+    assert_eq!(process("content"), 0);
+
+    fs::write(dir.join("files/b.txt"), "changed").unwrap();
+    assert_eq!(process("metadata"), 1);
+    assert_eq!(process("content"), 1);
+
+    // files are recorded as they were after they were processed, so files edited in place are
+    // skipped, and files that were moved away aren't recorded
+    fs::create_dir_all(dir.join("edited")).unwrap();
+    fs::write(dir.join("edited/c.txt"), "c").unwrap();
+    fs::write(dir.join("edited/d.txt"), "d").unwrap();
+    let mut conf: config::Config = toml::from_str(
+        r#"
+        [libraries.edit]
+        command = """
+        case '{{ file_path }}' in
+          *c.txt) printf 'edited' > '{{ file_path }}' ;;
+          *) mv '{{ file_path }}' tests/tmp/skip_if_processed/moved.txt ;;
+        esac
+        """
+        skip_if_processed = "metadata"
+
+          [libraries.edit.filter]
+          directories = [ "tests/tmp/skip_if_processed/edited" ]
+        "#,
+    )
+    .unwrap();
+    conf.libraries.get_mut("edit").unwrap().name = "edit".to_string();
+    let lib = Library::new(&conf, &conf.libraries["edit"], &dry_run).state(Some(&db));
+    assert_eq!(lib.process(None).unwrap().processed, 2);
+    assert_eq!(lib.process(None).unwrap().processed, 0);
+    assert!(db.get("edit", &dir.join("edited/d.txt")).unwrap().is_none());
+
+    // files that are gone by the time they're processed are skipped
+    let gone = dir.join("edited/gone.txt");
+    assert_eq!(lib.process(Some(&gone)).unwrap().processed, 0);
+}
+
+#[test]
+fn test_process_webhook() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.notify]
+        action = { type = "webhook", url = "http://127.0.0.1:1/{{ parent_dir | slugify }}", body = "{{ mime_type }}", headers = { X-Token = "{{ token }}" } }
+
+          [libraries.notify.vars]
+          token = "secret"
+
+          [libraries.notify.filter]
+          directories = [ "tests/files/text" ]
+
+        [libraries.default_body]
+        action = { type = "webhook", url = "http://127.0.0.1:1/" }
+
+          [libraries.default_body.vars]
+          token = "secret"
+
+          [libraries.default_body.filter]
+          directories = [ "tests/files/text" ]
+        "#,
+    )
+    .unwrap();
+    let dry_run = None;
+    let plain_path: PathBuf = ["tests", "files", "text", "plain"].iter().collect();
+
+    let notify_lib = Library::new(&conf, &conf.libraries["notify"], &dry_run);
+    let rendered = notify_lib.render(&plain_path).unwrap().unwrap();
+    assert_eq!(
+        rendered.commands,
+        vec!["POST to http://127.0.0.1:1/tests-files-text: text/plain".to_string()]
+    );
+
+    let default_lib = Library::new(&conf, &conf.libraries["default_body"], &dry_run);
+    let rendered = default_lib.render(&plain_path).unwrap().unwrap();
+    let body = rendered.commands[0]
+        .strip_prefix("POST to http://127.0.0.1:1/: ")
+        .unwrap();
+    let body: HashMap<String, String> = serde_json::from_str(body).unwrap();
+    assert_eq!(body["file_path"], plain_path.to_string_lossy());
+    assert_eq!(body["mime_type"], "text/plain");
+    assert!(!body.contains_key("token"));
+
+    // nothing listens on the URL, so the requests fail
+    let summary = notify_lib.process(None).unwrap();
+    assert_eq!(summary.processed, 0);
+    assert_eq!(summary.failed, 2);
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_batch() {
+    let conf = Arc::new(
+        toml::from_str::<config::Config>(
+            r#"
+            [libraries.batch]
+            command = "echo '{{ files | length }} {{ prefix }}' >> tests/tmp/batch_output"
+            batch = { max_files = 2 }
+
+              [libraries.batch.vars]
+              prefix = "batched"
+
+              [libraries.batch.filter]
+              directories = [ "tests/files/audio" ]
+              mime_type_regexes = [ "audio/.+" ]
+            "#,
+        )
+        .unwrap(),
+    );
+    let output_path: PathBuf = ["tests", "tmp", "batch_output"].iter().collect();
+    let _ = fs::remove_file(&output_path);
+
+    let dry_run = Some(DryRun::Text);
+    let lib = Library::new(&conf, &conf.libraries["batch"], &dry_run);
+    let flac_path: PathBuf = ["tests", "files", "audio", "flac"].iter().collect();
+    let rendered = lib.render(&flac_path).unwrap().unwrap();
+    assert_eq!(
+        rendered.commands,
+        vec!["echo '1 batched' >> tests/tmp/batch_output"]
+    );
+    assert_eq!(lib.process(None).unwrap().processed, 3);
+    assert!(!output_path.exists());
+
+    let pool = worker_pool::WorkerPool::new(2);
+    let summary =
+        process_with_pool(&conf, "batch", None, None, &pool, &Records::default()).unwrap();
+    assert_eq!(summary.processed, 3);
+    let mut lines: Vec<String> = fs::read_to_string(&output_path)
+        .unwrap()
+        .lines()
+        .map(|l| l.to_string())
+        .collect();
+    lines.sort();
+    assert_eq!(lines, vec!["1 batched", "2 batched"]);
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_stdin() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.file]
+        command = "cmp -s - '{{ file_path }}' && echo same > tests/tmp/stdin_file_output"
+        stdin = "file"
+
+          [libraries.file.filter]
+          directories = [ "tests/files/text" ]
+          mime_type_regexes = [ "text/plain" ]
+
+        [libraries.json]
+        command = "cat > tests/tmp/stdin_json_output"
+        stdin = "json"
+
+          [libraries.json.vars]
+          name = "json"
+
+          [libraries.json.filter]
+          directories = [ "tests/files/text" ]
+          mime_type_regexes = [ "text/plain" ]
+        "#,
+    )
+    .unwrap();
+    let dry_run = None;
+
+    let file_lib = Library::new(&conf, &conf.libraries["file"], &dry_run);
+    assert_eq!(file_lib.process(None).unwrap().processed, 1);
+    let output_path: PathBuf = ["tests", "tmp", "stdin_file_output"].iter().collect();
+    assert_eq!(fs::read_to_string(output_path).unwrap(), "same\n");
+
+    let json_lib = Library::new(&conf, &conf.libraries["json"], &dry_run);
+    assert_eq!(json_lib.process(None).unwrap().processed, 1);
+    let output_path: PathBuf = ["tests", "tmp", "stdin_json_output"].iter().collect();
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(output_path).unwrap()).unwrap();
+    assert_eq!(json["name"], "json");
+    assert_eq!(json["mime_type"], "text/plain");
+    assert!(json["file_path"].as_str().unwrap().ends_with("plain"));
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_hooks() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.hooks]
+        command = "echo 'broken {{ mime_type }}' >&2; test '{{ mime_type }}' = 'text/plain'"
+        on_success = "echo '{{ file_path }} {{ exit_code }}' >> tests/tmp/hooks_success"
+        on_failure = """
+        echo '{{ file_path }} {{ exit_code }} {{ stderr | trim }} {{ duration >= 0 }}' >> tests/tmp/hooks_failure
+        """
+
+          [libraries.hooks.filter]
+          directories = [ "tests/files/text" ]
+        "#,
+    )
+    .unwrap();
+    let dry_run = None;
+    let success_path: PathBuf = ["tests", "tmp", "hooks_success"].iter().collect();
+    let failure_path: PathBuf = ["tests", "tmp", "hooks_failure"].iter().collect();
+    let _ = fs::remove_file(&success_path);
+    let _ = fs::remove_file(&failure_path);
+
+    let lib = Library::new(&conf, &conf.libraries["hooks"], &dry_run);
+    let summary = lib.process(None).unwrap();
+    assert_eq!(summary.processed, 1);
+    assert_eq!(summary.failed, 1);
+
+    let plain_path: PathBuf = ["tests", "files", "text", "plain"].iter().collect();
+    let pdf_path: PathBuf = ["tests", "files", "text", "pdf"].iter().collect();
+    assert_eq!(
+        fs::read_to_string(success_path).unwrap(),
+        format!("{} 0\n", plain_path.display())
+    );
+    assert_eq!(
+        fs::read_to_string(failure_path).unwrap(),
+        format!("{} 1 broken application/pdf true\n", pdf_path.display())
+    );
+
+    // hooks that fail to render or start don't change the files' outcomes
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.render]
+        command = "true"
+        on_success = "echo {{ no_such_variable }}"
+
+          [libraries.render.filter]
+          directories = [ "tests/files/text" ]
+
+        [libraries.start]
+        command = "true"
+        on_success = "true"
+        # only the hook, which has the exit code, runs in a directory that doesn't exist
+        cwd = "{% if exit_code is defined %}tests/files/no-such-directory{% else %}.{% endif %}"
+
+          [libraries.start.filter]
+          directories = [ "tests/files/text" ]
+        "#,
+    )
+    .unwrap();
+    for cur_name in ["render", "start"] {
+        let lib = Library::new(&conf, &conf.libraries[cur_name], &dry_run);
+        let summary = lib.process(None).unwrap();
+        assert_eq!(summary.processed, 2);
+        assert_eq!(summary.failed, 0);
+    }
+}
+
+#[cfg(target_family = "unix")]
+#[test]
+fn test_process_log() {
+    let conf: config::Config = toml::from_str(
+        r#"
+        [libraries.logged]
+        command = "echo 'out {{ mime_type }}'; echo err >&2"
+        log = { path = "tests/tmp/logged/library.log" }
+
+          [libraries.logged.filter]
+          directories = [ "tests/files/text" ]
+          mime_type_regexes = [ "text/plain" ]
+        "#,
+    )
+    .unwrap();
+    let dry_run = None;
+    let log_path: PathBuf = ["tests", "tmp", "logged", "library.log"].iter().collect();
+    let _ = fs::remove_file(&log_path);
+
+    let lib = Library::new(&conf, &conf.libraries["logged"], &dry_run);
+    assert_eq!(lib.process(None).unwrap().processed, 1);
+    let plain_path: PathBuf = ["tests", "files", "text", "plain"].iter().collect();
+    let log = fs::read_to_string(&log_path).unwrap();
+    assert!(log.contains(&format!(
+        "'{}' stdout: out text/plain\n",
+        plain_path.display()
+    )));
+    assert!(log.contains(&format!("'{}' stderr: err\n", plain_path.display())));
+}
@@ -1,13 +1,24 @@
+mod action;
+mod checksum;
+mod command;
+mod command_log;
 mod config;
+mod events;
 mod fs_notify;
+mod journal;
 mod library;
 mod mime_type;
+mod shutdown;
+mod state;
 mod template;
+mod webhook;
+mod worker_pool;
 use clap::{Args, Parser, Subcommand};
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
@@ -34,9 +45,12 @@ enum Commands {
         /// Path to the configuration file to use
         #[clap(required = true)]
         config_path: String,
-        /// Print the file Librarian got a notification for, instead of running the pre-configured command against it
+        /// Print the command rendered for the file Librarian got a notification for, instead of running it
         #[clap(short, long)]
         dry_run: bool,
+        /// Print the dry run output as JSON, one object per file
+        #[clap(long, requires = "dry_run")]
+        json: bool,
     },
 
     /// Run Librarian once
@@ -45,9 +59,23 @@ enum Commands {
         /// Path to the configuration file to use
         #[clap(required = true)]
         config_path: String,
-        /// Print the filtered files, instead of running the pre-configured commands against them
+        /// Print the commands rendered for the filtered files, instead of running them
         #[clap(short, long)]
         dry_run: bool,
+        /// Print the dry run output as JSON, one object per file
+        #[clap(long, requires = "dry_run")]
+        json: bool,
+    },
+
+    /// Reverse the changes built-in file actions made during a run of Librarian, using the journal
+    #[clap(arg_required_else_help = true)]
+    Undo {
+        /// Path to the configuration file to use
+        #[clap(required = true)]
+        config_path: String,
+        /// ID of the run to reverse, printed when the run started
+        #[clap(required = true)]
+        run_id: String,
     },
 
     /// Debugging tools to help you to better work with Librarian
@@ -68,6 +96,17 @@ enum TestCommands {
         #[clap(value_parser)]
         file_path: String,
     },
+    /// Render a library's command against a file, and print it together with the variables available to the command's template, without running it
+    Render {
+        /// Path to the configuration file to use
+        #[clap(value_parser)]
+        config_path: String,
+        /// Name of the library, in the configuration file, whose command should be rendered
+        #[clap(value_parser)]
+        library: String,
+        #[clap(value_parser)]
+        file_path: String,
+    },
 }
 
 fn main() {
@@ -76,15 +115,33 @@ fn main() {
         Commands::Watch {
             config_path,
             dry_run,
+            json,
         } => {
-            watch(&config_path, dry_run);
+            let dry_run = get_dry_run(dry_run, json);
+            let records = get_records(&get_config(&config_path), &dry_run);
+            watch(&config_path, dry_run, records);
+            shutdown::wait_if_requested();
         }
         Commands::SingleShot {
             config_path,
             dry_run,
+            json,
         } => {
-            single_shot(&config_path, dry_run);
+            let conf = get_config(&config_path);
+            let dry_run = get_dry_run(dry_run, json);
+            let pool = Arc::new(worker_pool::WorkerPool::new(conf.max_parallel_commands()));
+            let records = get_records(&conf, &dry_run);
+            listen_for_shutdown(&conf, || {});
+            let ok = single_shot(&config_path, dry_run, &pool, &records);
+            shutdown::wait_if_requested();
+            if !ok {
+                std::process::exit(exitcode::DATAERR);
+            }
         }
+        Commands::Undo {
+            config_path,
+            run_id,
+        } => undo(&config_path, &run_id),
         Commands::Test(t) => {
             test(&t);
         }
@@ -101,21 +158,114 @@ fn get_config(config_path: &String) -> config::Config {
     }
 }
 
-fn watch(config_path: &String, dry_run: bool) {
+fn get_dry_run(dry_run: bool, json: bool) -> Option<library::DryRun> {
+    match (dry_run, json) {
+        (false, _) => None,
+        (true, false) => Some(library::DryRun::Text),
+        (true, true) => Some(library::DryRun::Json),
+    }
+}
+
+// opens everything, that's configured, libraries record what they do during the run in
+fn get_records(conf: &config::Config, dry_run: &Option<library::DryRun>) -> library::Records {
+    library::Records {
+        journal: get_journal(conf, dry_run),
+        events: get_events(conf, dry_run),
+        state: get_state(conf),
+    }
+}
+
+// opens the journal, if one is configured, for a new run. Dry runs don't change anything so
+// they don't need a journal
+fn get_journal(
+    conf: &config::Config,
+    dry_run: &Option<library::DryRun>,
+) -> Option<Arc<journal::Journal>> {
+    let path = match (&conf.journal, dry_run) {
+        (Some(p), None) => p,
+        _ => return None,
+    };
+
+    match journal::Journal::open(Path::new(path), journal::new_run_id()) {
+        Ok(j) => {
+            println!("Recording changes in the journal as run {}", j.run_id());
+            Some(Arc::new(j))
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exitcode::IOERR);
+        }
+    }
+}
+
+// opens the event stream, if one is configured. Nothing is processed during dry runs so no
+// records are written
+fn get_events(
+    conf: &config::Config,
+    dry_run: &Option<library::DryRun>,
+) -> Option<Arc<events::Sink>> {
+    let path = match (&conf.events, dry_run) {
+        (Some(p), None) => p,
+        _ => return None,
+    };
+
+    match events::Sink::open(Path::new(path)) {
+        Ok(e) => Some(Arc::new(e)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exitcode::IOERR);
+        }
+    }
+}
+
+// opens the state database, if one is configured. Dry runs don't update the database, but
+// still skip the files it says were already processed
+fn get_state(conf: &config::Config) -> Option<Arc<state::State>> {
+    let path = conf.state.as_ref()?;
+
+    match state::State::open(Path::new(path)) {
+        Ok(s) => Some(Arc::new(s)),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exitcode::IOERR);
+        }
+    }
+}
+
+fn listen_for_shutdown<F>(conf: &config::Config, on_signal: F)
+where
+    F: FnOnce() + Send + 'static,
+{
+    if let Err(e) = shutdown::listen(conf.shutdown_grace_period(), on_signal) {
+        eprintln!("{}", e);
+        std::process::exit(exitcode::OSERR);
+    }
+}
+
+fn watch(config_path: &String, dry_run: Option<library::DryRun>, records: library::Records) {
     let conf = get_config(config_path);
+    let pool = Arc::new(worker_pool::WorkerPool::new(conf.max_parallel_commands()));
 
     let mut paths: HashSet<PathBuf> = HashSet::new();
     // let mut libraries = Vec::new();
-    for cur_lib_config in conf.libraries {
-        for cur_dir in cur_lib_config.1.filter.directories {
+    for cur_lib_config in conf.libraries.values() {
+        for cur_dir in cur_lib_config.filter.directories.iter() {
             paths.insert(PathBuf::from(cur_dir));
         }
     }
 
     let (on_event_sender, on_event_receiver) = channel();
-    let (mut notify_obj, _) =
+    let (mut notify_obj, unwatch_sender) =
         fs_notify::Notify::new(&conf.fs_watch, paths, on_event_sender).unwrap();
+    // stop watching for new events once Librarian starts shutting down
+    listen_for_shutdown(&conf, move || {
+        if let Err(e) = fs_notify::Notify::unwatch(&unwatch_sender) {
+            eprintln!("{}", e);
+        }
+    });
     let config_path_clone = config_path.clone();
+    let event_pool = Arc::clone(&pool);
+    let event_records = records.clone();
     thread::spawn(move || loop {
         let path = match on_event_receiver.recv() {
             Ok(p) => p,
@@ -125,31 +275,51 @@ fn watch(config_path: &String, dry_run: bool) {
             }
         };
 
-        let conf = get_config(&config_path_clone);
-        for cur_lib_config in conf.libraries {
-            let cur_lib = library::Library::new(&cur_lib_config.1, &dry_run);
+        let conf = Arc::new(get_config(&config_path_clone));
+        for (cur_lib_name, cur_lib_config) in conf.libraries.iter() {
+            let cur_lib = library::Library::new(&conf, cur_lib_config, &dry_run);
             if !cur_lib.contains_path(Path::new(&path)) {
                 continue;
             }
 
-            let number = match cur_lib.process(Some(Path::new(&path))) {
-                Ok(n) => n,
-                Err(e) => {
-                    eprintln!("{}", e);
-                    0
-                }
-            };
+            let conf = Arc::clone(&conf);
+            let cur_lib_name = cur_lib_name.clone();
+            let path = path.clone();
+            let records = event_records.clone();
+            event_pool.execute(
+                cur_lib_name.clone().as_str(),
+                cur_lib_config.concurrency(),
+                move || {
+                    let cur_lib =
+                        library::Library::new(&conf, &conf.libraries[&cur_lib_name], &dry_run)
+                            .records(&records);
+                    let summary = match cur_lib.process(Some(Path::new(&path))) {
+                        Ok(s) => s,
+                        Err(e) => {
+                            eprintln!("{}", e);
+                            library::Summary::default()
+                        }
+                    };
 
-            if number > 0 {
-                println!(
-                    "Processed '{}' as part of the {} library",
-                    path, cur_lib_config.0
-                );
-            }
+                    // JSON dry runs only print the rendered commands to stdout
+                    if summary.processed > 0 && dry_run != Some(library::DryRun::Json) {
+                        println!(
+                            "Processed '{}' as part of the {} library",
+                            path, cur_lib_name
+                        );
+                    }
+                    if summary.failed > 0 {
+                        eprintln!(
+                            "Failed to process '{}' as part of the {} library",
+                            path, cur_lib_name
+                        );
+                    }
+                },
+            );
         }
     });
 
-    single_shot(config_path, dry_run);
+    single_shot(config_path, dry_run, &pool, &records);
     if let Err(e) = notify_obj.watch() {
         eprint!(
             "An error was thrown while attempting to watch a library: {:?}",
@@ -158,15 +328,37 @@ fn watch(config_path: &String, dry_run: bool) {
     }
 }
 
-fn single_shot(config_path: &String, dry_run: bool) {
-    let conf = get_config(config_path);
+// processes all the libraries once, returning whether all of them were processed without errors
+fn single_shot(
+    config_path: &String,
+    dry_run: Option<library::DryRun>,
+    pool: &Arc<worker_pool::WorkerPool>,
+    records: &library::Records,
+) -> bool {
+    let conf = Arc::new(get_config(config_path));
 
-    for cur_conf in conf.libraries {
+    for cur_lib_name in conf.libraries.keys() {
         GLOBAL_THREAD_COUNT.fetch_add(1, Ordering::SeqCst);
+        let conf = Arc::clone(&conf);
+        let cur_lib_name = cur_lib_name.clone();
+        let pool = Arc::clone(pool);
+        let records = records.clone();
         thread::spawn(move || {
-            match library::Library::new(&cur_conf.1, &dry_run).process(None) {
+            match library::process_with_pool(&conf, &cur_lib_name, dry_run, None, &pool, &records) {
                 Ok(k) => {
-                    println!("Processed {} files in the {} library", k, cur_conf.0);
+                    if dry_run != Some(library::DryRun::Json) {
+                        println!(
+                            "Processed {} files in the {} library",
+                            k.processed, cur_lib_name
+                        );
+                    }
+                    if k.failed > 0 {
+                        eprintln!(
+                            "The command failed for {} files in the {} library",
+                            k.failed, cur_lib_name
+                        );
+                        GLOBAL_FAILED_TREADS.fetch_add(1, Ordering::SeqCst);
+                    }
                 }
                 Err(e) => {
                     eprintln!("{}", e);
@@ -182,14 +374,42 @@ fn single_shot(config_path: &String, dry_run: bool) {
         thread::sleep(Duration::from_millis(1));
     }
 
-    if GLOBAL_FAILED_TREADS.load(Ordering::SeqCst) > 0 {
-        std::process::exit(exitcode::DATAERR);
+    GLOBAL_FAILED_TREADS.load(Ordering::SeqCst) == 0
+}
+
+fn undo(config_path: &String, run_id: &str) {
+    let conf = get_config(config_path);
+    let path = match &conf.journal {
+        Some(p) => p,
+        None => {
+            eprintln!("A journal is not configured");
+            std::process::exit(exitcode::CONFIG);
+        }
+    };
+
+    match journal::undo(Path::new(path), run_id) {
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exitcode::DATAERR);
+        }
+        Ok(s) => {
+            println!("Undid {} changes from run {}", s.undone, run_id);
+            if s.failed > 0 {
+                eprintln!("Could not undo {} changes from run {}", s.failed, run_id);
+                std::process::exit(exitcode::DATAERR);
+            }
+        }
     }
 }
 
 fn test(test: &Test) {
     match &test.command {
         TestCommands::Mime { file_path } => mime(file_path),
+        TestCommands::Render {
+            config_path,
+            library,
+            file_path,
+        } => render(config_path, library, file_path),
     }
 }
 
@@ -204,3 +424,52 @@ fn mime(file_path: &str) {
         }
     };
 }
+
+fn render(config_path: &String, library: &String, file_path: &str) {
+    let conf = get_config(config_path);
+    let lib_config = match conf.libraries.get(library) {
+        Some(l) => l,
+        None => {
+            eprintln!("The {} library is not defined in the config", library);
+            std::process::exit(exitcode::CONFIG);
+        }
+    };
+
+    let dry_run = Some(library::DryRun::Text);
+    let lib = library::Library::new(&conf, lib_config, &dry_run);
+    let path = Path::new(file_path);
+    if !lib.contains_path(path) {
+        eprintln!(
+            "'{}' is not in any of the directories of the {} library",
+            file_path, library
+        );
+        std::process::exit(exitcode::DATAERR);
+    }
+
+    match lib.render(path) {
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exitcode::DATAERR);
+        }
+        Ok(None) => {
+            eprintln!(
+                "'{}' does not match the filters of the {} library",
+                file_path, library
+            );
+            std::process::exit(exitcode::DATAERR);
+        }
+        Ok(Some(r)) => {
+            for (i, cur_command) in r.commands.iter().enumerate() {
+                if r.commands.len() == 1 {
+                    println!("Command:\n{}", cur_command);
+                } else {
+                    println!("Step {} command:\n{}", i, cur_command);
+                }
+            }
+            println!("Variables:");
+            for (cur_key, cur_val) in r.context.iter() {
+                println!("  {} = {:?}", cur_key, cur_val);
+            }
+        }
+    };
+}
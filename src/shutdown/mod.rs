@@ -0,0 +1,146 @@
+use crate::command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests;
+
+// how often to check for signals, and whether the running commands have finished
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[cfg(target_family = "unix")]
+const SIGNALS: [(i32, &str); 3] = [
+    (signal_hook::consts::SIGINT, "SIGINT"),
+    (signal_hook::consts::SIGTERM, "SIGTERM"),
+    (signal_hook::consts::SIGHUP, "SIGHUP"),
+];
+#[cfg(not(target_family = "unix"))]
+const SIGNALS: [(i32, &str); 2] = [
+    (signal_hook::consts::SIGINT, "SIGINT"),
+    (signal_hook::consts::SIGTERM, "SIGTERM"),
+];
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+// the number of actions, like moving a file or sending a webhook, that are running. They run in
+// Librarian's own process, so unlike commands they can't be killed, only waited for
+static ACTIONS: Mutex<usize> = Mutex::new(0);
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("An IO error was thrown while trying to handle signals")]
+    Io(#[from] std::io::Error),
+}
+
+/// An action that's running, which shutting down waits for until it's dropped
+pub struct Action(());
+
+impl Drop for Action {
+    fn drop(&mut self) {
+        *lock_actions() -= 1;
+    }
+}
+
+/// Shuts Librarian down once it receives SIGINT, SIGTERM or SIGHUP. New commands and actions
+/// stop being started, and `on_signal` is called, so that no new files get processed. The
+/// commands and actions that are running get up to the grace period to finish, after which
+/// the commands, and any processes they started, are killed. Librarian then exits with 128
+/// plus the signal's number. Receiving a second signal ends the wait without waiting for the
+/// grace period to end
+pub fn listen<F>(grace_period: Duration, on_signal: F) -> Result<(), Error>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let received = Arc::new(AtomicUsize::new(0));
+    for (cur_signal, _) in SIGNALS.iter() {
+        signal_hook::flag::register_usize(
+            *cur_signal,
+            Arc::clone(&received),
+            *cur_signal as usize,
+        )?;
+    }
+
+    thread::spawn(move || {
+        let signal = loop {
+            match received.swap(0, Ordering::SeqCst) {
+                0 => thread::sleep(POLL_INTERVAL),
+                s => break s as i32,
+            }
+        };
+        let name = SIGNALS
+            .iter()
+            .find(|(s, _)| *s == signal)
+            .map(|(_, n)| *n)
+            .unwrap_or("a signal");
+
+        println!(
+            "Received {}, waiting up to {} seconds for running commands and actions to finish",
+            name,
+            grace_period.as_secs()
+        );
+        {
+            let _actions = lock_actions();
+            REQUESTED.store(true, Ordering::SeqCst);
+        }
+        command::stop();
+        on_signal();
+
+        let deadline = Instant::now() + grace_period;
+        loop {
+            let running = command::running();
+            let actions = *lock_actions();
+            if running == 0 && actions == 0 {
+                break;
+            }
+            if Instant::now() >= deadline || received.load(Ordering::SeqCst) != 0 {
+                if running != 0 {
+                    eprintln!("Killing {} commands that are still running", running);
+                    command::kill_running();
+                }
+                if actions != 0 {
+                    eprintln!("Exiting with {} actions still running", actions);
+                }
+                break;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        println!("Shut down after receiving {}", name);
+        std::process::exit(128 + signal);
+    });
+
+    Ok(())
+}
+
+/// Marks an action as running until the returned `Action` is dropped, so that shutting down
+/// waits for it to finish. Returns `None` if Librarian is shutting down, in which case the
+/// action shouldn't be started
+pub fn start_action() -> Option<Action> {
+    let mut actions = lock_actions();
+    if REQUESTED.load(Ordering::SeqCst) {
+        return None;
+    }
+    *actions += 1;
+
+    Some(Action(()))
+}
+
+/// Blocks forever if Librarian is shutting down, leaving it to the shutdown to exit the
+/// process once the running commands have finished
+pub fn wait_if_requested() {
+    if !REQUESTED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    loop {
+        thread::park();
+    }
+}
+
+fn lock_actions() -> MutexGuard<'static, usize> {
+    match ACTIONS.lock() {
+        Ok(a) => a,
+        Err(p) => p.into_inner(),
+    }
+}
@@ -0,0 +1,156 @@
+use super::*;
+use std::env;
+use std::process;
+
+// stopping commands, and the running commands, are global to the process, so the tests that
+// depend on them run in their own process, where no other tests are running commands
+const IN_OWN_PROCESS_VAR: &str = "LIBRARIAN_SHUTDOWN_TEST";
+
+fn run_in_own_process(test: &str) -> process::Output {
+    process::Command::new(env::current_exe().unwrap())
+        .args([
+            test,
+            "--exact",
+            "--ignored",
+            "--nocapture",
+            "--test-threads=1",
+        ])
+        .env(IN_OWN_PROCESS_VAR, "1")
+        .output()
+        .unwrap()
+}
+
+fn in_own_process() -> bool {
+    env::var_os(IN_OWN_PROCESS_VAR).is_some()
+}
+
+fn wait_for_running(count: usize) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while command::running() != count {
+        assert!(Instant::now() < deadline, "{} commands never ran", count);
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[test]
+fn test_stop() {
+    let output = run_in_own_process("shutdown::tests::stop_in_own_process");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("1 passed"));
+}
+
+#[test]
+#[ignore = "run by test_stop in its own process"]
+fn stop_in_own_process() {
+    if !in_own_process() {
+        return;
+    }
+
+    // the running commands are forgotten however they end
+    let output = command::Command::new("true".to_string()).run().unwrap();
+    assert!(output.success());
+    assert_eq!(command::running(), 0);
+    let output = command::Command::new("exit 3".to_string()).run().unwrap();
+    assert!(!output.success());
+    assert_eq!(command::running(), 0);
+    let output = command::Command::new("sleep 30".to_string())
+        .timeout(Some(Duration::from_millis(100)))
+        .run()
+        .unwrap();
+    assert!(output.timed_out);
+    assert_eq!(command::running(), 0);
+    assert!(command::Command::new("cat".to_string())
+        .stdin(command::Stdin::File("tests/files/no-such-file".into()))
+        .run()
+        .is_err());
+    assert_eq!(command::running(), 0);
+
+    let start = Instant::now();
+    let sleeping = thread::spawn(|| command::Command::new("sleep 30".to_string()).run());
+    wait_for_running(1);
+    command::kill_running();
+    let output = sleeping.join().unwrap().unwrap();
+    assert!(!output.success());
+    assert_eq!(output.exit_code, None);
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(command::running(), 0);
+
+    command::stop();
+    let err = command::Command::new("true".to_string()).run().unwrap_err();
+    assert!(matches!(err, command::Error::Stopped));
+    assert_eq!(command::running(), 0);
+}
+
+#[test]
+fn test_listen() {
+    let start = Instant::now();
+    let output = run_in_own_process("shutdown::tests::listen_in_own_process");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + signal_hook::consts::SIGTERM),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Received SIGTERM"));
+    assert!(stdout.contains("Shut down after receiving SIGTERM"));
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+#[ignore = "run by test_listen in its own process"]
+fn listen_in_own_process() {
+    if !in_own_process() {
+        return;
+    }
+
+    listen(Duration::from_millis(200), || {}).unwrap();
+    // outlives the grace period, so it's killed
+    thread::spawn(|| command::Command::new("sleep 30".to_string()).run());
+    wait_for_running(1);
+    nix::sys::signal::raise(nix::sys::signal::Signal::SIGTERM).unwrap();
+
+    // the process should exit before this ends
+    thread::sleep(Duration::from_secs(30));
+    panic!("didn't exit after receiving SIGTERM");
+}
+
+#[test]
+fn test_listen_waits_for_actions() {
+    let output = run_in_own_process("shutdown::tests::listen_waits_for_actions_in_own_process");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        output.status.code(),
+        Some(128 + signal_hook::consts::SIGTERM),
+        "{}",
+        stdout
+    );
+    let finished = stdout.find("Action finished").unwrap();
+    let shut_down = stdout.find("Shut down after receiving SIGTERM").unwrap();
+    assert!(finished < shut_down, "{}", stdout);
+    assert!(stdout.contains("No new actions"));
+}
+
+#[test]
+#[ignore = "run by test_listen_waits_for_actions in its own process"]
+fn listen_waits_for_actions_in_own_process() {
+    if !in_own_process() {
+        return;
+    }
+
+    listen(Duration::from_secs(10), || {}).unwrap();
+    let action = start_action().unwrap();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(300));
+        assert!(start_action().is_none());
+        println!("No new actions");
+        println!("Action finished");
+        drop(action);
+    });
+    nix::sys::signal::raise(nix::sys::signal::Signal::SIGTERM).unwrap();
+
+    // the process should exit before this ends
+    thread::sleep(Duration::from_secs(30));
+    panic!("didn't exit after receiving SIGTERM");
+}
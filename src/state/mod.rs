@@ -0,0 +1,189 @@
+use crate::checksum;
+use crate::journal;
+use serde_derive::Deserialize;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+mod tests;
+
+// how long to wait for another Librarian process to finish writing to the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+const HASH_ALGORITHM: checksum::Algorithm = checksum::Algorithm::Blake3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("An IO error was thrown while reading the state of '{0}'")]
+    Io(String, #[source] io::Error),
+    #[error("An error was thrown while accessing the state database")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("A checksum error was thrown while reading the state of a file")]
+    Checksum(#[from] checksum::Error),
+}
+
+/// When a file a library already processed is skipped
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SkipIfProcessed {
+    /// The file's size, modification time and inode haven't changed
+    Metadata,
+    /// The file's size and contents haven't changed
+    Content,
+}
+
+/// What a library last saw of a file it processed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileState {
+    pub size: u64,
+    /// Nanoseconds since the Unix epoch
+    pub mtime: i64,
+    /// Is 0 on operating systems without inodes
+    pub inode: u64,
+    /// The BLAKE3 checksum of the file's contents, only calculated when skipping files with
+    /// unchanged contents
+    pub hash: Option<String>,
+    /// How processing the file last ended, like `processed` or `failed`
+    pub outcome: String,
+}
+
+impl FileState {
+    /// Reads the file's current state, with an empty outcome. Returns `None` if the file
+    /// doesn't exist
+    pub fn read(path: &Path, with_hash: bool) -> Result<Option<FileState>, Error> {
+        let io_err = |e| Error::Io(path.display().to_string(), e);
+        let metadata = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_err(e)),
+        };
+        let mtime = metadata
+            .modified()
+            .map_err(io_err)?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or_default();
+        #[cfg(target_family = "unix")]
+        let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(target_family = "unix"))]
+        let inode = 0;
+        let hash = match with_hash {
+            true => checksum::File::new(path)
+                .get_checksums(&[HASH_ALGORITHM])?
+                .remove(&HASH_ALGORITHM),
+            false => None,
+        };
+
+        Ok(Some(FileState {
+            size: metadata.len(),
+            mtime,
+            inode,
+            hash,
+            outcome: String::new(),
+        }))
+    }
+
+    /// Whether the file, in its current state, is the same as when it was last processed
+    pub fn is_unchanged(&self, current: &FileState, policy: SkipIfProcessed) -> bool {
+        self.size == current.size
+            && match policy {
+                SkipIfProcessed::Metadata => {
+                    self.mtime == current.mtime && self.inode == current.inode
+                }
+                SkipIfProcessed::Content => self.hash.is_some() && self.hash == current.hash,
+            }
+    }
+}
+
+/// An SQLite database of the files every library processed, and how processing them ended
+#[derive(Debug)]
+pub struct State {
+    conn: Mutex<rusqlite::Connection>,
+}
+
+impl State {
+    pub fn open(path: &Path) -> Result<State, Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| Error::Io(path.display().to_string(), e))?;
+        }
+        let conn = rusqlite::Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS files (
+                library TEXT NOT NULL,
+                path TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                mtime INTEGER NOT NULL,
+                inode INTEGER NOT NULL,
+                hash TEXT,
+                outcome TEXT NOT NULL,
+                updated INTEGER NOT NULL,
+                PRIMARY KEY (library, path)
+            );",
+        )?;
+
+        Ok(State {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Returns the state of the file when the library last processed it
+    pub fn get(&self, library: &str, path: &Path) -> Result<Option<FileState>, Error> {
+        let path = key(path)?;
+        let conn = self.lock();
+        let mut stmt = conn.prepare_cached(
+            "SELECT size, mtime, inode, hash, outcome FROM files WHERE library = ?1 AND path = ?2",
+        )?;
+        let mut rows = stmt.query((library, path))?;
+        match rows.next()? {
+            Some(r) => Ok(Some(FileState {
+                size: r.get::<_, i64>(0)? as u64,
+                mtime: r.get(1)?,
+                inode: r.get::<_, i64>(2)? as u64,
+                hash: r.get(3)?,
+                outcome: r.get(4)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&self, library: &str, path: &Path, state: &FileState) -> Result<(), Error> {
+        let path = key(path)?;
+        let updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        self.lock().prepare_cached(
+            "INSERT OR REPLACE INTO files (library, path, size, mtime, inode, hash, outcome, updated)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?
+        .execute((
+            library,
+            path,
+            state.size as i64,
+            state.mtime,
+            state.inode as i64,
+            &state.hash,
+            &state.outcome,
+            updated,
+        ))?;
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, rusqlite::Connection> {
+        match self.conn.lock() {
+            Ok(c) => c,
+            Err(p) => p.into_inner(),
+        }
+    }
+}
+
+// files are stored by their absolute paths so that they're found whatever directory Librarian
+// runs in
+fn key(path: &Path) -> Result<String, Error> {
+    let path = journal::absolute(path).map_err(|e| Error::Io(path.display().to_string(), e))?;
+    Ok(path.to_string_lossy().to_string())
+}
@@ -0,0 +1,80 @@
+use super::*;
+use std::path::PathBuf;
+
+fn tmp_path(name: &str) -> PathBuf {
+    ["tests", "tmp", "state", name].iter().collect()
+}
+
+#[test]
+fn test_get_set() {
+    let db_path = tmp_path("get_set.db");
+    let _ = fs::remove_file(&db_path);
+    let file_path = tmp_path("get_set_file");
+    fs::create_dir_all(tmp_path("")).unwrap();
+    fs::write(&file_path, "contents").unwrap();
+
+    let state = State::open(&db_path).unwrap();
+    assert_eq!(state.get("music", &file_path).unwrap(), None);
+    let file_state = FileState {
+        outcome: "processed".to_string(),
+        ..FileState::read(&file_path, true).unwrap().unwrap()
+    };
+    assert_eq!(file_state.size, 8);
+    assert!(file_state.hash.is_some());
+    assert_eq!(
+        FileState::read(&tmp_path("no_such_file"), true).unwrap(),
+        None
+    );
+    state.set("music", &file_path, &file_state).unwrap();
+
+    // the state persists, and is found using the file's absolute path
+    drop(state);
+    let state = State::open(&db_path).unwrap();
+    let absolute_path = std::env::current_dir().unwrap().join(&file_path);
+    assert_eq!(
+        state.get("music", &absolute_path).unwrap(),
+        Some(file_state.clone())
+    );
+    assert_eq!(state.get("videos", &file_path).unwrap(), None);
+
+    let failed = FileState {
+        outcome: "failed".to_string(),
+        ..file_state
+    };
+    state.set("music", &file_path, &failed).unwrap();
+    assert_eq!(state.get("music", &file_path).unwrap(), Some(failed));
+}
+
+#[test]
+fn test_is_unchanged() {
+    let old = FileState {
+        size: 8,
+        mtime: 100,
+        inode: 5,
+        hash: Some("abc".to_string()),
+        outcome: "processed".to_string(),
+    };
+    assert!(old.is_unchanged(&old.clone(), SkipIfProcessed::Metadata));
+    assert!(old.is_unchanged(&old.clone(), SkipIfProcessed::Content));
+
+    let touched = FileState {
+        mtime: 200,
+        ..old.clone()
+    };
+    assert!(!old.is_unchanged(&touched, SkipIfProcessed::Metadata));
+    assert!(old.is_unchanged(&touched, SkipIfProcessed::Content));
+
+    let modified = FileState {
+        hash: Some("def".to_string()),
+        ..old.clone()
+    };
+    assert!(old.is_unchanged(&modified, SkipIfProcessed::Metadata));
+    assert!(!old.is_unchanged(&modified, SkipIfProcessed::Content));
+
+    let resized = FileState {
+        size: 9,
+        ..old.clone()
+    };
+    assert!(!old.is_unchanged(&resized, SkipIfProcessed::Metadata));
+    assert!(!old.is_unchanged(&resized, SkipIfProcessed::Content));
+}
@@ -1,29 +1,233 @@
 use std::collections;
+use tera::ast;
 
 #[cfg(test)]
 mod tests;
 
+// prefix of the names the templates are registered under, alongside any shared snippets
+const TEMPLATE_NAME_PREFIX: &str = "__librarian_template_";
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("A Tera templating error occurred")]
     Io(#[from] tera::Error),
 }
 
+/// Templates compiled into one Tera instance, along with the snippets they can include or
+/// import, so that every snippet is only parsed once
+#[derive(Debug)]
+pub struct Set {
+    tera: tera::Tera,
+    num_templates: usize,
+}
+
+/// A template in a `Set`, rendered using `Set::render`
+#[derive(Debug)]
 pub struct Template {
-    tmpl: String,
+    name: String,
+    // the variables used by the template, and by the snippets it includes or imports
+    variables: collections::HashSet<String>,
+}
+
+impl Set {
+    /// Creates a set whose templates can `{% include %}` or `{% import %}` any of the provided
+    /// snippets using the snippet's name
+    pub fn with_snippets(snippets: &collections::HashMap<String, String>) -> Result<Set, Error> {
+        let mut tera = tera::Tera::default();
+        tera.autoescape_on(vec![]);
+        tera.add_raw_templates(
+            snippets
+                .iter()
+                .map(|(name, content)| (name.as_str(), content.as_str())),
+        )?;
+
+        Ok(Set {
+            tera,
+            num_templates: 0,
+        })
+    }
+
+    /// Adds the template to the set
+    pub fn add(&mut self, tmpl: &str) -> Result<Template, Error> {
+        let name = format!("{}{}__", TEMPLATE_NAME_PREFIX, self.num_templates);
+        self.tera.add_raw_template(&name, tmpl)?;
+        self.num_templates += 1;
+        let variables = variables(&self.tera, &name);
+
+        Ok(Template { name, variables })
+    }
+
+    /// Adds a template for a Tera expression, like `mime_type == "audio/flac"`, that can be
+    /// evaluated using `Set::render_condition`
+    pub fn add_condition(&mut self, expr: &str) -> Result<Template, Error> {
+        self.add(format!("{{% if {} %}}true{{% endif %}}", expr).as_str())
+    }
+
+    /// Renders the template using a context that can hold values that aren't strings
+    pub fn render(&self, template: &Template, context: &tera::Context) -> Result<String, Error> {
+        Ok(self.tera.render(&template.name, context)?)
+    }
+
+    /// Renders a template added using `Set::add_condition`, returning whether the expression
+    /// is true
+    pub fn render_condition(
+        &self,
+        template: &Template,
+        context: &tera::Context,
+    ) -> Result<bool, Error> {
+        Ok(self.render(template, context)? == "true")
+    }
 }
 
 impl Template {
-    pub fn new(tmpl: String) -> Result<Template, Error> {
-        Ok(Template { tmpl })
+    /// Checks whether the variable is used by the template, or by any of the snippets it
+    /// includes or imports. Used to avoid computing variables that are expensive to compute
+    /// but aren't used
+    pub fn references(&self, var: &str) -> bool {
+        self.variables.contains(var)
     }
+}
 
-    pub fn render(&self, data: &collections::HashMap<&str, &str>) -> Result<String, Error> {
-        let mut context = tera::Context::new();
-        for (cur_key, cur_val) in data {
-            context.insert(*cur_key, *cur_val);
+// returns the names of the variables used by the template, and by the templates it includes,
+// imports or extends, however deeply
+fn variables(tera: &tera::Tera, name: &str) -> collections::HashSet<String> {
+    let mut variables = collections::HashSet::new();
+    let mut visited = collections::HashSet::new();
+    let mut pending = vec![name.to_string()];
+    while let Some(cur_name) = pending.pop() {
+        if !visited.insert(cur_name.clone()) {
+            continue;
         }
+        // a missing template is an error once the template is rendered
+        let cur_template = match tera.get_template(&cur_name) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
 
-        Ok(tera::Tera::one_off(self.tmpl.as_str(), &context, false)?)
+        pending.extend(cur_template.parent.iter().cloned());
+        pending.extend(
+            cur_template
+                .imported_macro_files
+                .iter()
+                .map(|(f, _)| f.clone()),
+        );
+        visit_nodes(&cur_template.ast, &mut pending, &mut variables);
+    }
+
+    variables
+}
+
+// adds the variables used by the nodes to variables, and the templates they include to
+// includes
+fn visit_nodes(
+    nodes: &[ast::Node],
+    includes: &mut Vec<String>,
+    variables: &mut collections::HashSet<String>,
+) {
+    for cur_node in nodes {
+        match cur_node {
+            ast::Node::VariableBlock(_, e) => visit_expr(e, variables),
+            ast::Node::MacroDefinition(_, m, _) => {
+                for cur_default in m.args.values().flatten() {
+                    visit_expr(cur_default, variables);
+                }
+                visit_nodes(&m.body, includes, variables);
+            }
+            ast::Node::Include(_, names, _) => includes.extend(names.iter().cloned()),
+            ast::Node::Set(_, s) => visit_expr(&s.value, variables),
+            ast::Node::FilterSection(_, f, _) => {
+                visit_call(&f.filter, variables);
+                visit_nodes(&f.body, includes, variables);
+            }
+            ast::Node::Block(_, b, _) => visit_nodes(&b.body, includes, variables),
+            ast::Node::Forloop(_, f, _) => {
+                visit_expr(&f.container, variables);
+                visit_nodes(&f.body, includes, variables);
+                if let Some(b) = &f.empty_body {
+                    visit_nodes(b, includes, variables);
+                }
+            }
+            ast::Node::If(i, _) => {
+                for (_, cur_cond, cur_body) in i.conditions.iter() {
+                    visit_expr(cur_cond, variables);
+                    visit_nodes(cur_body, includes, variables);
+                }
+                if let Some((_, b)) = &i.otherwise {
+                    visit_nodes(b, includes, variables);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn visit_expr(expr: &ast::Expr, variables: &mut collections::HashSet<String>) {
+    visit_val(&expr.val, variables);
+    for cur_filter in expr.filters.iter() {
+        visit_call(cur_filter, variables);
+    }
+}
+
+fn visit_call(call: &ast::FunctionCall, variables: &mut collections::HashSet<String>) {
+    for cur_arg in call.args.values() {
+        visit_expr(cur_arg, variables);
+    }
+}
+
+fn visit_val(val: &ast::ExprVal, variables: &mut collections::HashSet<String>) {
+    match val {
+        ast::ExprVal::Ident(i) => add_ident(i, variables),
+        ast::ExprVal::Math(m) => {
+            visit_expr(&m.lhs, variables);
+            visit_expr(&m.rhs, variables);
+        }
+        ast::ExprVal::Logic(l) => {
+            visit_expr(&l.lhs, variables);
+            visit_expr(&l.rhs, variables);
+        }
+        ast::ExprVal::Test(t) => {
+            add_ident(&t.ident, variables);
+            for cur_arg in t.args.iter() {
+                visit_expr(cur_arg, variables);
+            }
+        }
+        ast::ExprVal::MacroCall(m) => {
+            for cur_arg in m.args.values() {
+                visit_expr(cur_arg, variables);
+            }
+        }
+        ast::ExprVal::FunctionCall(f) => visit_call(f, variables),
+        ast::ExprVal::Array(a) => {
+            for cur_expr in a.iter() {
+                visit_expr(cur_expr, variables);
+            }
+        }
+        ast::ExprVal::StringConcat(c) => {
+            for cur_val in c.values.iter() {
+                visit_val(cur_val, variables);
+            }
+        }
+        ast::ExprVal::In(i) => {
+            visit_expr(&i.lhs, variables);
+            visit_expr(&i.rhs, variables);
+        }
+        _ => {}
+    }
+}
+
+// adds the variable an identifier, like `steps.0.stdout` or `checksums[alg]`, starts with, and
+// any variables used to index it
+fn add_ident(ident: &str, variables: &mut collections::HashSet<String>) {
+    // every other part is between brackets, and is either a literal or a variable
+    for (i, cur_part) in ident.split(['[', ']']).enumerate() {
+        if i % 2 == 1 && !cur_part.starts_with(|c: char| c.is_alphabetic() || c == '_') {
+            continue;
+        }
+        match cur_part.split('.').next() {
+            Some(r) if !r.is_empty() => {
+                variables.insert(r.to_string());
+            }
+            _ => {}
+        }
     }
 }
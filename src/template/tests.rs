@@ -1,5 +1,18 @@
 use super::*;
 
+fn render(
+    set: &Set,
+    template: &Template,
+    data: &collections::HashMap<&str, &str>,
+) -> Result<String, Error> {
+    let mut context = tera::Context::new();
+    for (cur_key, cur_val) in data {
+        context.insert(*cur_key, *cur_val);
+    }
+
+    set.render(template, &context)
+}
+
 #[test]
 fn test_render() {
     let ok_test_cases: Vec<(&str, collections::HashMap<&str, &str>, &str)> = vec![
@@ -21,10 +34,11 @@ fn test_render() {
         ),
     ];
 
+    let mut set = Set::with_snippets(&collections::HashMap::new()).unwrap();
     for cur_test_case in ok_test_cases.iter() {
-        let cur_template = Template::new(cur_test_case.0.to_string()).unwrap();
+        let cur_template = set.add(cur_test_case.0).unwrap();
         assert_eq!(
-            cur_template.render(&cur_test_case.1).unwrap(),
+            render(&set, &cur_template, &cur_test_case.1).unwrap(),
             cur_test_case.2
         );
     }
@@ -36,11 +50,136 @@ fn test_render() {
     )];
 
     for cur_test_case in err_test_cases.iter() {
-        let cur_template = Template::new(cur_test_case.0.to_string()).unwrap();
-        assert!(cur_template
-            .render(&cur_test_case.1)
+        let cur_template = set.add(cur_test_case.0).unwrap();
+        assert!(render(&set, &cur_template, &cur_test_case.1)
             .unwrap_err()
             .to_string()
             .contains(cur_test_case.2));
     }
 }
+
+#[test]
+fn test_render_with_snippets() {
+    let snippets: collections::HashMap<String, String> = [
+        (
+            "move.sh".to_string(),
+            "mkdir -p \"{{ dest }}\" && mv \"{{ file_path }}\" \"{{ dest }}/\"".to_string(),
+        ),
+        (
+            "macros.tera".to_string(),
+            "{% macro quote(value) %}'{{ value }}'{% endmacro quote %}".to_string(),
+        ),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+    let data: collections::HashMap<&str, &str> = [("file_path", "/tmp/a.flac"), ("dest", "/music")]
+        .iter()
+        .cloned()
+        .collect();
+
+    let ok_test_cases = [
+        (
+            "{% include \"move.sh\" %}",
+            "mkdir -p \"/music\" && mv \"/tmp/a.flac\" \"/music/\"",
+        ),
+        (
+            "{% import \"macros.tera\" as m %}ls {{ m::quote(value=file_path) }}",
+            "ls '/tmp/a.flac'",
+        ),
+    ];
+    // the templates share the snippets, and are rendered by name
+    let mut set = Set::with_snippets(&snippets).unwrap();
+    let templates: Vec<Template> = ok_test_cases
+        .iter()
+        .map(|t| set.add(t.0).unwrap())
+        .collect();
+    for (cur_template, cur_test_case) in templates.iter().zip(ok_test_cases.iter()) {
+        assert_eq!(render(&set, cur_template, &data).unwrap(), cur_test_case.1);
+    }
+
+    assert!(set
+        .add("{% include \"missing.sh\" %}")
+        .and_then(|t| render(&set, &t, &data))
+        .is_err());
+}
+
+#[test]
+fn test_references() {
+    let snippets: collections::HashMap<String, String> = [
+        ("hash".to_string(), "{% include \"md5\" %}".to_string()),
+        ("md5".to_string(), "{{ md5 }}".to_string()),
+        ("unused".to_string(), "{{ blake3 }}".to_string()),
+        (
+            "macros.tera".to_string(),
+            "{% macro dest() %}{{ parent_dir }}{% endmacro dest %}".to_string(),
+        ),
+    ]
+    .iter()
+    .cloned()
+    .collect();
+    let mut set = Set::with_snippets(&snippets).unwrap();
+    let cur_template = set
+        .add(
+            "{% import \"macros.tera\" as m %}exiftool -md5 {{ file_path }} /md5/{% include \"hash\" %}/\
+         {{ sha256 | truncate(length=2, end='') }}/{{ m::dest() }}/{{ steps.0.stdout }}/\
+         {{ checksums[sha1] }}",
+        )
+        .unwrap();
+    for cur_var in [
+        "file_path",
+        "sha256",
+        "md5",
+        "parent_dir",
+        "steps",
+        "checksums",
+        "sha1",
+    ] {
+        assert!(cur_template.references(cur_var), "{}", cur_var);
+    }
+    for cur_var in ["blake3", "sha", "stdout", "exiftool", "m"] {
+        assert!(!cur_template.references(cur_var), "{}", cur_var);
+    }
+
+    // literal text that looks like a variable isn't a reference
+    let cur_template = set.add("exiftool -md5 /md5/ {{ file_path }}").unwrap();
+    assert!(!cur_template.references("md5"));
+
+    let cur_template = set
+        .add_condition("mime_type is starting_with(prefix)")
+        .unwrap();
+    assert!(cur_template.references("mime_type"));
+    assert!(cur_template.references("prefix"));
+}
+
+#[test]
+fn test_render_condition() {
+    let mut context = tera::Context::new();
+    context.insert("mime_type", "audio/flac");
+    context.insert(
+        "steps",
+        &vec![[("stdout", "42")]
+            .iter()
+            .cloned()
+            .collect::<collections::HashMap<&str, &str>>()],
+    );
+
+    let ok_test_cases = [
+        ("mime_type == 'audio/flac'", true),
+        ("mime_type is starting_with('video/')", false),
+        ("steps.0.stdout | int > 40", true),
+    ];
+    let mut set = Set::with_snippets(&collections::HashMap::new()).unwrap();
+    for cur_test_case in ok_test_cases.iter() {
+        let cur_template = set.add_condition(cur_test_case.0).unwrap();
+        assert_eq!(
+            set.render_condition(&cur_template, &context).unwrap(),
+            cur_test_case.1
+        );
+    }
+
+    assert!(set
+        .add_condition("undefined_var == 1")
+        .and_then(|t| set.render_condition(&t, &context))
+        .is_err());
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+const DEFAULT_TIMEOUT: u64 = 30;
+const DEFAULT_CONTENT_TYPE: &str = "application/json";
+// the longest part of an error response's body included in errors
+const MAX_ERROR_BODY_CHARS: usize = 200;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("The webhook at {0} responded with status {1}: {2}")]
+    Status(String, u16, String),
+    #[error("The request to the webhook at {0} failed")]
+    Request(String, #[source] Box<ureq::Transport>),
+}
+
+impl Error {
+    // whether sending the request again could succeed
+    fn is_retryable(&self) -> bool {
+        match self {
+            Error::Status(_, s, _) => *s == 429 || *s >= 500,
+            Error::Request(..) => true,
+        }
+    }
+}
+
+/// An HTTP POST request sent to notify another service about a file
+#[derive(Debug)]
+pub struct Webhook {
+    url: String,
+    body: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    retries: u32,
+    retry_backoff: Duration,
+}
+
+impl fmt::Display for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "POST to {}: {}", self.url, self.body)
+    }
+}
+
+impl Webhook {
+    pub fn new(url: String, body: String) -> Webhook {
+        Webhook {
+            url,
+            body,
+            headers: Vec::new(),
+            timeout: Duration::from_secs(DEFAULT_TIMEOUT),
+            retries: 0,
+            retry_backoff: Duration::ZERO,
+        }
+    }
+
+    /// Sets the request's headers. The body is sent as JSON unless a `Content-Type` is set
+    pub fn headers(mut self, headers: Vec<(String, String)>) -> Webhook {
+        self.headers = headers;
+        self
+    }
+
+    /// Sets the maximum amount of time a request, including connecting and reading the
+    /// response, can take. Defaults to 30 seconds
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Webhook {
+        self.timeout = timeout.unwrap_or(Duration::from_secs(DEFAULT_TIMEOUT));
+        self
+    }
+
+    /// Sets the number of times the request is retried if it fails to connect, times out, or
+    /// the server responds with a 429 or 5xx status, and how long to wait before the first
+    /// retry. The wait is doubled for every other retry
+    pub fn retries(mut self, retries: u32, backoff: Duration) -> Webhook {
+        self.retries = retries;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    pub fn send(&self) -> Result<(), Error> {
+        let agent = ureq::AgentBuilder::new().timeout(self.timeout).build();
+        let mut attempt: u32 = 0;
+        loop {
+            let err = match self.send_once(&agent) {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            if attempt >= self.retries || !err.is_retryable() {
+                return Err(err);
+            }
+
+            let cur_backoff = self
+                .retry_backoff
+                .checked_mul(2u32.saturating_pow(attempt))
+                .unwrap_or(Duration::MAX);
+            eprintln!("{}, retrying in {:?}", err, cur_backoff);
+            thread::sleep(cur_backoff);
+            attempt += 1;
+        }
+    }
+
+    fn send_once(&self, agent: &ureq::Agent) -> Result<(), Error> {
+        let mut request = agent.post(&self.url);
+        if !self
+            .headers
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+        {
+            request = request.set("Content-Type", DEFAULT_CONTENT_TYPE);
+        }
+        for (cur_key, cur_val) in self.headers.iter() {
+            request = request.set(cur_key, cur_val);
+        }
+
+        match request.send_string(&self.body) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(status, response)) => {
+                let body: String = response
+                    .into_string()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(MAX_ERROR_BODY_CHARS)
+                    .collect();
+                Err(Error::Status(
+                    self.url.clone(),
+                    status,
+                    body.trim().to_string(),
+                ))
+            }
+            Err(ureq::Error::Transport(t)) => Err(Error::Request(self.url.clone(), Box::new(t))),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use super::*;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+
+// starts an HTTP server that responds to each request with the next of the statuses, and sends
+// the headers and body of every request it gets through the returned channel
+fn serve(statuses: Vec<u16>) -> (String, mpsc::Receiver<(Vec<String>, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for cur_status in statuses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut headers = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                headers.push(line.trim().to_lowercase());
+            }
+            let length: usize = headers
+                .iter()
+                .find_map(|h| h.strip_prefix("content-length: "))
+                .map(|l| l.parse().unwrap())
+                .unwrap_or(0);
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            reader
+                .get_mut()
+                .write_all(
+                    format!(
+                        "HTTP/1.1 {} Status\r\nContent-Length: 2\r\n\r\nno",
+                        cur_status
+                    )
+                    .as_bytes(),
+                )
+                .unwrap();
+            sender
+                .send((headers, String::from_utf8(body).unwrap()))
+                .unwrap();
+        }
+    });
+
+    (url, receiver)
+}
+
+#[test]
+fn test_send() {
+    let (url, requests) = serve(vec![200]);
+    Webhook::new(url, "{\"file_path\":\"a.pdf\"}".to_string())
+        .headers(vec![("X-Token".to_string(), "secret".to_string())])
+        .send()
+        .unwrap();
+    let (headers, body) = requests.recv().unwrap();
+    assert_eq!(headers[0], "post /hook http/1.1");
+    assert!(headers.contains(&"content-type: application/json".to_string()));
+    assert!(headers.contains(&"x-token: secret".to_string()));
+    assert_eq!(body, "{\"file_path\":\"a.pdf\"}");
+}
+
+#[test]
+fn test_send_retries() {
+    let (url, requests) = serve(vec![503, 500, 200]);
+    Webhook::new(url, "body".to_string())
+        .headers(vec![("Content-Type".to_string(), "text/plain".to_string())])
+        .retries(2, Duration::from_millis(10))
+        .send()
+        .unwrap();
+    for _ in 0..3 {
+        let (headers, _) = requests.recv().unwrap();
+        assert!(headers.contains(&"content-type: text/plain".to_string()));
+    }
+
+    // client errors aren't retried
+    let (url, requests) = serve(vec![404, 200]);
+    let err = Webhook::new(url, "body".to_string())
+        .retries(2, Duration::from_millis(10))
+        .send()
+        .unwrap_err();
+    assert!(matches!(err, Error::Status(_, 404, ref b) if b == "no"));
+    requests.recv().unwrap();
+    assert!(requests.recv_timeout(Duration::from_millis(100)).is_err());
+
+    let err = Webhook::new("http://127.0.0.1:1/hook".to_string(), "body".to_string())
+        .send()
+        .unwrap_err();
+    assert!(matches!(err, Error::Request(..)));
+}
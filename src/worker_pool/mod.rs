@@ -0,0 +1,148 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+#[cfg(test)]
+mod tests;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+#[derive(Default)]
+struct State {
+    // pending jobs, queued separately for each key so that a key that has reached its limit
+    // doesn't hold up jobs for the other keys
+    queues: HashMap<String, VecDeque<Job>>,
+    // order in which keys get picked, rotated after every pick so keys take turns
+    keys: VecDeque<String>,
+    running: HashMap<String, usize>,
+    limits: HashMap<String, usize>,
+    stopping: bool,
+}
+
+impl State {
+    fn next_job(&mut self) -> Option<(String, Job)> {
+        for i in 0..self.keys.len() {
+            let key = &self.keys[i];
+            let running = self.running.get(key).copied().unwrap_or(0);
+            if running >= self.limits.get(key).copied().unwrap_or(1) {
+                continue;
+            }
+
+            let job = match self.queues.get_mut(key).and_then(|q| q.pop_front()) {
+                Some(j) => j,
+                None => continue,
+            };
+            let key = key.clone();
+            *self.running.entry(key.clone()).or_insert(0) += 1;
+            self.keys.rotate_left(i + 1);
+
+            return Some((key, job));
+        }
+
+        None
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queues.values().all(|q| q.is_empty())
+    }
+}
+
+/// A fixed number of threads that run queued jobs. Every job has a key, and the number of
+/// jobs with the same key that can run at the same time can be limited
+pub struct WorkerPool {
+    state: Arc<(Mutex<State>, Condvar)>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    pub fn new(size: usize) -> WorkerPool {
+        let state: Arc<(Mutex<State>, Condvar)> = Arc::default();
+        let workers = (0..size.max(1))
+            .map(|_| {
+                let state = Arc::clone(&state);
+                thread::spawn(move || work(&state))
+            })
+            .collect();
+
+        WorkerPool { state, workers }
+    }
+
+    /// Queues the job. The job runs once a thread is free and fewer than `limit` jobs with the
+    /// same key are running
+    pub fn execute<F>(&self, key: &str, limit: usize, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let (lock, cvar) = &*self.state;
+        let mut state = match lock.lock() {
+            Ok(s) => s,
+            Err(p) => p.into_inner(),
+        };
+
+        if !state.queues.contains_key(key) {
+            state.keys.push_back(key.to_string());
+        }
+        state.limits.insert(key.to_string(), limit.max(1));
+        state
+            .queues
+            .entry(key.to_string())
+            .or_default()
+            .push_back(Box::new(job));
+        cvar.notify_all();
+    }
+}
+
+impl Drop for WorkerPool {
+    // waits for all the queued jobs to run before stopping the threads
+    fn drop(&mut self) {
+        {
+            let (lock, cvar) = &*self.state;
+            let mut state = match lock.lock() {
+                Ok(s) => s,
+                Err(p) => p.into_inner(),
+            };
+            state.stopping = true;
+            cvar.notify_all();
+        }
+
+        for cur_worker in self.workers.drain(..) {
+            let _ = cur_worker.join();
+        }
+    }
+}
+
+fn work(state: &(Mutex<State>, Condvar)) {
+    let (lock, cvar) = state;
+    loop {
+        let (key, job) = {
+            let mut state = match lock.lock() {
+                Ok(s) => s,
+                Err(p) => p.into_inner(),
+            };
+            loop {
+                if let Some(j) = state.next_job() {
+                    break j;
+                }
+                if state.stopping && state.is_empty() {
+                    return;
+                }
+                state = match cvar.wait(state) {
+                    Ok(s) => s,
+                    Err(p) => p.into_inner(),
+                };
+            }
+        };
+
+        // a panicking job shouldn't take the thread, or the key's running count, with it
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+
+        let mut state = match lock.lock() {
+            Ok(s) => s,
+            Err(p) => p.into_inner(),
+        };
+        if let Some(r) = state.running.get_mut(&key) {
+            *r -= 1;
+        }
+        cvar.notify_all();
+    }
+}
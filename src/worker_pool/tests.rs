@@ -0,0 +1,60 @@
+use super::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+#[test]
+fn test_execute() {
+    let pool = WorkerPool::new(4);
+    let total_running = Arc::new(AtomicUsize::new(0));
+    let max_total_running = Arc::new(AtomicUsize::new(0));
+    let limited_running = Arc::new(AtomicUsize::new(0));
+    let max_limited_running = Arc::new(AtomicUsize::new(0));
+    let (done_sender, done_receiver) = channel();
+
+    for i in 0..40 {
+        let key = if i % 2 == 0 { "limited" } else { "other" };
+        let limit = if key == "limited" { 2 } else { 10 };
+        let counters = [
+            (Arc::clone(&total_running), Arc::clone(&max_total_running)),
+            (
+                Arc::clone(&limited_running),
+                Arc::clone(&max_limited_running),
+            ),
+        ];
+        let done_sender = done_sender.clone();
+        pool.execute(key, limit, move || {
+            let num_counters = if key == "limited" { 2 } else { 1 };
+            for (running, max_running) in counters.iter().take(num_counters) {
+                let cur = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(cur, Ordering::SeqCst);
+            }
+            thread::sleep(Duration::from_millis(5));
+            for (running, _) in counters.iter().take(num_counters) {
+                running.fetch_sub(1, Ordering::SeqCst);
+            }
+            done_sender.send(()).unwrap();
+        });
+    }
+    drop(done_sender);
+
+    assert_eq!(done_receiver.iter().count(), 40);
+    assert!(max_total_running.load(Ordering::SeqCst) <= 4);
+    assert!(max_limited_running.load(Ordering::SeqCst) <= 2);
+}
+
+#[test]
+fn test_drop_runs_queued_jobs() {
+    let num_run = Arc::new(AtomicUsize::new(0));
+    {
+        let pool = WorkerPool::new(1);
+        for _ in 0..5 {
+            let num_run = Arc::clone(&num_run);
+            pool.execute("key", 1, move || {
+                num_run.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+    }
+
+    assert_eq!(num_run.load(Ordering::SeqCst), 5);
+}
@@ -0,0 +1,25 @@
+use std::env::consts::OS;
+use std::process::Command;
+
+#[test]
+fn test_single_shot_dry_run_json() {
+    let config_path = if OS == "windows" {
+        "tests\\configs\\good-windows.toml"
+    } else {
+        "tests/configs/good.toml"
+    };
+    let output = Command::new(env!("CARGO_BIN_EXE_fs-librarian"))
+        .args(["single-shot", "--dry-run", "--json", config_path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // only the rendered commands are printed to stdout, so that it can be piped to a JSON parser
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.lines().count() > 0);
+    for cur_line in stdout.lines() {
+        let json: serde_json::Value = serde_json::from_str(cur_line)
+            .unwrap_or_else(|e| panic!("'{}' isn't JSON: {}", cur_line, e));
+        assert!(json["library"].is_string());
+    }
+}